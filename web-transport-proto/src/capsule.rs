@@ -7,22 +7,103 @@ use crate::{VarInt, VarIntUnexpectedEnd};
 // decodes to 808. There may be a discrepancy in implementations or specs.
 // Using 0x2843 as specified in the standard.
 const CLOSE_WEBTRANSPORT_SESSION_TYPE: u64 = 0x2843;
+
+// RFC 9297 Section 3.5
+const DATAGRAM_TYPE: u64 = 0x00;
+
+// Session-level flow control, see draft-ietf-webtrans-http3 Section 5.6.
+const WT_MAX_DATA_TYPE: u64 = 0x190b4d3d;
+const WT_MAX_STREAMS_BIDI_TYPE: u64 = 0x190b4d3f;
+const WT_MAX_STREAMS_UNI_TYPE: u64 = 0x190b4d40;
+const WT_DATA_BLOCKED_TYPE: u64 = 0x190b4d41;
+const WT_STREAMS_BLOCKED_BIDI_TYPE: u64 = 0x190b4d43;
+const WT_STREAMS_BLOCKED_UNI_TYPE: u64 = 0x190b4d44;
 const MAX_MESSAGE_SIZE: usize = 1024;
 
+/// The maximum payload size of a [Capsule::Datagram].
+///
+/// Datagrams sent over the CONNECT stream aren't limited by the path MTU, but we still don't want to buffer too much.
+pub const MAX_DATAGRAM_SIZE: usize = 8 * 1024;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Capsule {
-    CloseWebTransportSession { code: u32, reason: String },
-    Unknown { typ: VarInt, payload: Bytes },
+    CloseWebTransportSession {
+        code: u32,
+        reason: String,
+    },
+
+    /// A datagram sent reliably on the CONNECT stream, used when QUIC datagrams are unavailable.
+    Datagram {
+        payload: Bytes,
+    },
+
+    /// The maximum number of bytes that can be sent on all streams in the session.
+    WtMaxData {
+        max: u64,
+    },
+
+    /// The maximum number of streams that can be opened in the session, cumulative.
+    WtMaxStreams {
+        bidi: bool,
+        max: u64,
+    },
+
+    /// The sender wanted to send data but was blocked by the session limit.
+    WtDataBlocked {
+        max: u64,
+    },
+
+    /// The sender wanted to open a stream but was blocked by the session limit.
+    WtStreamsBlocked {
+        bidi: bool,
+        max: u64,
+    },
+
+    Unknown {
+        typ: VarInt,
+        payload: Bytes,
+    },
 }
 
 impl Capsule {
+    /// Returns the capsule type as encoded on the wire.
+    pub fn typ(&self) -> VarInt {
+        match self {
+            Self::CloseWebTransportSession { .. } => {
+                VarInt::from_u64(CLOSE_WEBTRANSPORT_SESSION_TYPE).unwrap()
+            }
+            Self::Datagram { .. } => VarInt::from_u64(DATAGRAM_TYPE).unwrap(),
+            Self::WtMaxData { .. } => VarInt::from_u64(WT_MAX_DATA_TYPE).unwrap(),
+            Self::WtMaxStreams { bidi: true, .. } => {
+                VarInt::from_u64(WT_MAX_STREAMS_BIDI_TYPE).unwrap()
+            }
+            Self::WtMaxStreams { bidi: false, .. } => {
+                VarInt::from_u64(WT_MAX_STREAMS_UNI_TYPE).unwrap()
+            }
+            Self::WtDataBlocked { .. } => VarInt::from_u64(WT_DATA_BLOCKED_TYPE).unwrap(),
+            Self::WtStreamsBlocked { bidi: true, .. } => {
+                VarInt::from_u64(WT_STREAMS_BLOCKED_BIDI_TYPE).unwrap()
+            }
+            Self::WtStreamsBlocked { bidi: false, .. } => {
+                VarInt::from_u64(WT_STREAMS_BLOCKED_UNI_TYPE).unwrap()
+            }
+            Self::Unknown { typ, .. } => *typ,
+        }
+    }
+
     pub fn decode<B: Buf>(buf: &mut B) -> Result<Self, CapsuleError> {
         loop {
-            let typ = VarInt::decode(buf)?;
-            let length = VarInt::decode(buf)?;
+            // A partial header just means we need more data.
+            let typ = VarInt::decode(buf).map_err(|_| CapsuleError::UnexpectedEnd)?;
+            let length = VarInt::decode(buf).map_err(|_| CapsuleError::UnexpectedEnd)?;
+
+            let max = match typ.into_inner() {
+                DATAGRAM_TYPE => MAX_DATAGRAM_SIZE,
+                _ => MAX_MESSAGE_SIZE,
+            };
 
             let mut payload = buf.take(length.into_inner() as usize);
-            if payload.remaining() > MAX_MESSAGE_SIZE {
+            if payload.remaining() > max {
                 return Err(CapsuleError::MessageTooLong);
             }
 
@@ -54,6 +135,28 @@ impl Capsule {
                         reason: error_message,
                     });
                 }
+                DATAGRAM_TYPE => {
+                    let payload = payload.copy_to_bytes(payload.remaining());
+                    return Ok(Self::Datagram { payload });
+                }
+                WT_MAX_DATA_TYPE => {
+                    let max = decode_limit(&mut payload)?;
+                    return Ok(Self::WtMaxData { max });
+                }
+                WT_MAX_STREAMS_BIDI_TYPE | WT_MAX_STREAMS_UNI_TYPE => {
+                    let bidi = typ.into_inner() == WT_MAX_STREAMS_BIDI_TYPE;
+                    let max = decode_limit(&mut payload)?;
+                    return Ok(Self::WtMaxStreams { bidi, max });
+                }
+                WT_DATA_BLOCKED_TYPE => {
+                    let max = decode_limit(&mut payload)?;
+                    return Ok(Self::WtDataBlocked { max });
+                }
+                WT_STREAMS_BLOCKED_BIDI_TYPE | WT_STREAMS_BLOCKED_UNI_TYPE => {
+                    let bidi = typ.into_inner() == WT_STREAMS_BLOCKED_BIDI_TYPE;
+                    let max = decode_limit(&mut payload)?;
+                    return Ok(Self::WtStreamsBlocked { bidi, max });
+                }
                 t if is_grease(t) => continue,
                 _ => {
                     // Unknown capsule type - store it
@@ -89,6 +192,22 @@ impl Capsule {
                 // Encode the error message
                 buf.put_slice(error_message.as_bytes());
             }
+            Self::Datagram { payload } => {
+                self.typ().encode(buf);
+                VarInt::try_from(payload.len()).unwrap().encode(buf);
+                buf.put_slice(payload);
+            }
+            Self::WtMaxData { max }
+            | Self::WtMaxStreams { max, .. }
+            | Self::WtDataBlocked { max }
+            | Self::WtStreamsBlocked { max, .. } => {
+                // The payload is a single VarInt.
+                let max = VarInt::from_u64(*max).unwrap();
+
+                self.typ().encode(buf);
+                VarInt::from_u32(max.size() as u32).encode(buf);
+                max.encode(buf);
+            }
             Self::Unknown { typ, payload } => {
                 // Encode the capsule type
                 typ.encode(buf);
@@ -103,6 +222,16 @@ impl Capsule {
     }
 }
 
+// Decode a flow control payload, which must be a single VarInt.
+fn decode_limit<B: Buf>(payload: &mut B) -> Result<u64, CapsuleError> {
+    let max = VarInt::decode(payload).map_err(|_| CapsuleError::InvalidLength)?;
+    if payload.has_remaining() {
+        return Err(CapsuleError::InvalidLength);
+    }
+
+    Ok(max.into_inner())
+}
+
 fn is_grease(val: u64) -> bool {
     if val < 0x21 {
         return false;
@@ -121,6 +250,9 @@ pub enum CapsuleError {
     #[error("message too long")]
     MessageTooLong,
 
+    #[error("invalid length")]
+    InvalidLength,
+
     #[error("unknown capsule type: {0:?}")]
     UnknownType(VarInt),
 
@@ -257,6 +389,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_flow_control_roundtrip() {
+        let capsules = [
+            Capsule::WtMaxData { max: 1 << 20 },
+            Capsule::WtMaxStreams {
+                bidi: true,
+                max: 100,
+            },
+            Capsule::WtMaxStreams {
+                bidi: false,
+                max: 0,
+            },
+            Capsule::WtDataBlocked { max: 12345 },
+            Capsule::WtStreamsBlocked { bidi: true, max: 7 },
+            Capsule::WtStreamsBlocked {
+                bidi: false,
+                max: 8,
+            },
+        ];
+
+        for capsule in capsules {
+            let mut buf = Vec::new();
+            capsule.encode(&mut buf);
+
+            let mut read_buf = buf.as_slice();
+            let decoded = Capsule::decode(&mut read_buf).unwrap();
+
+            assert_eq!(capsule, decoded);
+            assert_eq!(read_buf.len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_datagram_roundtrip() {
+        let capsule = Capsule::Datagram {
+            payload: Bytes::from(vec![0xab; MAX_DATAGRAM_SIZE]),
+        };
+
+        let mut buf = Vec::new();
+        capsule.encode(&mut buf);
+
+        let mut read_buf = buf.as_slice();
+        let decoded = Capsule::decode(&mut read_buf).unwrap();
+
+        assert_eq!(capsule, decoded);
+        assert_eq!(read_buf.len(), 0);
+    }
+
+    #[test]
+    fn test_flow_control_trailing_data() {
+        // WT_MAX_DATA with an extra byte after the VarInt.
+        let mut data = Vec::new();
+        VarInt::from_u64(0x190b4d3d).unwrap().encode(&mut data);
+        VarInt::from_u32(2).encode(&mut data);
+        data.extend_from_slice(b"\x01\x02");
+
+        let mut buf = data.as_slice();
+        let result = Capsule::decode(&mut buf);
+        assert!(matches!(result, Err(CapsuleError::InvalidLength)));
+    }
+
     #[test]
     fn test_unknown_capsule_roundtrip() {
         let capsule = Capsule::Unknown {
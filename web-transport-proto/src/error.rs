@@ -16,3 +16,28 @@ pub fn error_from_http3(code: u64) -> Option<u32> {
 pub fn error_to_http3(code: u32) -> u64 {
     ERROR_FIRST + code as u64 + code as u64 / 0x1e
 }
+
+// HTTP/3 error codes, used to close the connection or reset streams.
+// See RFC 9114 Section 8.1.
+pub const H3_NO_ERROR: u64 = 0x100;
+pub const H3_GENERAL_PROTOCOL_ERROR: u64 = 0x101;
+pub const H3_INTERNAL_ERROR: u64 = 0x102;
+pub const H3_STREAM_CREATION_ERROR: u64 = 0x103;
+pub const H3_CLOSED_CRITICAL_STREAM: u64 = 0x104;
+pub const H3_FRAME_UNEXPECTED: u64 = 0x105;
+pub const H3_FRAME_ERROR: u64 = 0x106;
+pub const H3_EXCESSIVE_LOAD: u64 = 0x107;
+pub const H3_ID_ERROR: u64 = 0x108;
+pub const H3_SETTINGS_ERROR: u64 = 0x109;
+pub const H3_MISSING_SETTINGS: u64 = 0x10a;
+pub const H3_REQUEST_REJECTED: u64 = 0x10b;
+pub const H3_REQUEST_CANCELLED: u64 = 0x10c;
+pub const H3_REQUEST_INCOMPLETE: u64 = 0x10d;
+pub const H3_MESSAGE_ERROR: u64 = 0x10e;
+pub const H3_CONNECT_ERROR: u64 = 0x10f;
+pub const H3_VERSION_FALLBACK: u64 = 0x110;
+
+// WebTransport error codes, used to reset streams or close the session.
+// See draft-ietf-webtrans-http3 Section 9.5.
+pub const WT_BUFFERED_STREAM_REJECTED: u64 = 0x3994bd84;
+pub const WT_FLOW_CONTROL_ERROR: u64 = 0x045d4487;
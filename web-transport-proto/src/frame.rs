@@ -57,6 +57,10 @@ macro_rules! frames {
 frames! {
     DATA = 0x00,
     HEADERS = 0x01,
+    CANCEL_PUSH = 0x03,
     SETTINGS = 0x04,
+    PUSH_PROMISE = 0x05,
+    GOAWAY = 0x07,
+    MAX_PUSH_ID = 0x0d,
     WEBTRANSPORT = 0x41,
 }
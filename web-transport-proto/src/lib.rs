@@ -1,7 +1,11 @@
+// `u64::is_multiple_of` needs Rust 1.87; keep the `%` form for older toolchains.
+#![allow(clippy::manual_is_multiple_of)]
+
 mod capsule;
 mod connect;
 mod error;
 mod frame;
+mod request;
 mod settings;
 mod stream;
 mod varint;
@@ -10,6 +14,7 @@ pub use capsule::*;
 pub use connect::*;
 pub use error::*;
 pub use frame::*;
+pub use request::*;
 pub use settings::*;
 pub use stream::*;
 pub use varint::*;
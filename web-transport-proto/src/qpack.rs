@@ -51,6 +51,10 @@ impl Headers {
         self.fields.insert(name.to_string(), value.to_string());
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
     pub fn decode<B: Buf>(mut buf: &mut B) -> Result<Self, DecodeError> {
         // We don't support dynamic entries so we can skip these.
         let (_, _insert_count) = decode_prefix(buf, 8)?;
@@ -0,0 +1,265 @@
+use std::str::FromStr;
+
+use bytes::{Buf, BufMut};
+
+use super::{qpack, Frame, VarInt};
+
+use thiserror::Error;
+
+// Errors that can occur when decoding a plain HTTP/3 request or response.
+#[derive(Error, Debug, Clone)]
+pub enum HttpError {
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+
+    #[error("qpack error")]
+    QpackError(#[from] qpack::DecodeError),
+
+    #[error("unexpected frame {0:?}")]
+    UnexpectedFrame(Frame),
+
+    #[error("invalid method")]
+    InvalidMethod,
+
+    #[error("expected path header")]
+    WrongPath,
+
+    #[error("invalid status")]
+    InvalidStatus,
+
+    #[error("invalid header: {0}")]
+    InvalidHeader(String),
+}
+
+// A plain HTTP/3 request, such as a GET, sent in a HEADERS frame.
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: http::Method,
+
+    // The host, which is optional if a Host header is used instead.
+    pub authority: Option<String>,
+
+    // The path and query.
+    pub path: String,
+
+    // Any regular headers, excluding pseudo-headers.
+    pub headers: http::HeaderMap,
+}
+
+impl HttpRequest {
+    pub fn decode<B: Buf>(buf: &mut B) -> Result<Self, HttpError> {
+        let headers = decode_headers(buf)?;
+
+        let method = headers.get(":method").ok_or(HttpError::InvalidMethod)?;
+        let method = http::Method::from_str(method).map_err(|_| HttpError::InvalidMethod)?;
+        let authority = headers.get(":authority").map(str::to_string);
+        let path = headers
+            .get(":path")
+            .ok_or(HttpError::WrongPath)?
+            .to_string();
+
+        Ok(Self {
+            method,
+            authority,
+            path,
+            headers: header_map(&headers)?,
+        })
+    }
+
+    pub fn encode<B: BufMut>(&self, buf: &mut B) {
+        let mut headers = qpack::Headers::default();
+        headers.set(":method", self.method.as_str());
+        headers.set(":scheme", "https");
+        if let Some(authority) = &self.authority {
+            headers.set(":authority", authority);
+        }
+        headers.set(":path", &self.path);
+
+        encode_headers(headers, &self.headers, buf)
+    }
+}
+
+// The response to a plain HTTP/3 request, sent in a HEADERS frame before any DATA frames.
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: http::StatusCode,
+
+    // Any regular headers, excluding pseudo-headers.
+    pub headers: http::HeaderMap,
+}
+
+impl HttpResponse {
+    pub fn decode<B: Buf>(buf: &mut B) -> Result<Self, HttpError> {
+        let headers = decode_headers(buf)?;
+
+        let status = headers.get(":status").ok_or(HttpError::InvalidStatus)?;
+        let status = http::StatusCode::from_str(status).map_err(|_| HttpError::InvalidStatus)?;
+
+        Ok(Self {
+            status,
+            headers: header_map(&headers)?,
+        })
+    }
+
+    pub fn encode<B: BufMut>(&self, buf: &mut B) {
+        let mut headers = qpack::Headers::default();
+        headers.set(":status", self.status.as_str());
+
+        encode_headers(headers, &self.headers, buf)
+    }
+}
+
+// Read a HEADERS frame and decode the field section.
+fn decode_headers<B: Buf>(buf: &mut B) -> Result<qpack::Headers, HttpError> {
+    let (typ, mut data) = Frame::read(buf).map_err(|_| HttpError::UnexpectedEnd)?;
+    if typ != Frame::HEADERS {
+        return Err(HttpError::UnexpectedFrame(typ));
+    }
+
+    Ok(qpack::Headers::decode(&mut data)?)
+}
+
+// Convert the regular headers into a HeaderMap, skipping the pseudo-headers.
+fn header_map(headers: &qpack::Headers) -> Result<http::HeaderMap, HttpError> {
+    let mut map = http::HeaderMap::new();
+
+    for (name, value) in headers.iter().filter(|(name, _)| !name.starts_with(':')) {
+        let name = http::HeaderName::from_str(name)
+            .map_err(|_| HttpError::InvalidHeader(name.to_string()))?;
+        let value = http::HeaderValue::from_str(value)
+            .map_err(|_| HttpError::InvalidHeader(name.to_string()))?;
+        map.append(name, value);
+    }
+
+    Ok(map)
+}
+
+// Encode the pseudo-headers and regular headers as a HEADERS frame.
+fn encode_headers<B: BufMut>(mut headers: qpack::Headers, map: &http::HeaderMap, buf: &mut B) {
+    // NOTE: Our QPACK implementation only supports one value per header name, so duplicates are joined.
+    for name in map.keys() {
+        let values: Vec<_> = map
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        headers.set(name.as_str(), &values.join(", "));
+    }
+
+    // Use a temporary buffer so we can compute the size.
+    let mut tmp = Vec::new();
+    headers.encode(&mut tmp);
+    let size = VarInt::from_u32(tmp.len() as u32);
+
+    Frame::HEADERS.encode(buf);
+    size.encode(buf);
+    buf.put_slice(&tmp);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("user-agent", "test".parse().unwrap());
+        headers.append("accept", "text/plain".parse().unwrap());
+        headers.append("accept", "text/html".parse().unwrap());
+
+        let request = HttpRequest {
+            method: http::Method::GET,
+            authority: Some("localhost:4443".to_string()),
+            path: "/health?verbose=1".to_string(),
+            headers,
+        };
+
+        let mut buf = Vec::new();
+        request.encode(&mut buf);
+
+        let mut read_buf = buf.as_slice();
+        let decoded = HttpRequest::decode(&mut read_buf).unwrap();
+        assert_eq!(read_buf.len(), 0); // All bytes consumed
+
+        assert_eq!(decoded.method, http::Method::GET);
+        assert_eq!(decoded.authority.as_deref(), Some("localhost:4443"));
+        assert_eq!(decoded.path, "/health?verbose=1");
+        assert_eq!(decoded.headers["user-agent"], "test");
+
+        // Duplicate headers are joined, since our QPACK only supports one value per name.
+        assert_eq!(decoded.headers["accept"], "text/plain, text/html");
+
+        // Pseudo-headers aren't included.
+        assert!(decoded
+            .headers
+            .keys()
+            .all(|name| !name.as_str().starts_with(':')));
+    }
+
+    #[test]
+    fn test_request_without_authority() {
+        let request = HttpRequest {
+            method: http::Method::POST,
+            authority: None,
+            path: "/".to_string(),
+            headers: http::HeaderMap::new(),
+        };
+
+        let mut buf = Vec::new();
+        request.encode(&mut buf);
+
+        let decoded = HttpRequest::decode(&mut buf.as_slice()).unwrap();
+        assert_eq!(decoded.method, http::Method::POST);
+        assert_eq!(decoded.authority, None);
+        assert_eq!(decoded.path, "/");
+        assert!(decoded.headers.is_empty());
+    }
+
+    #[test]
+    fn test_response_roundtrip() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("content-type", "text/plain".parse().unwrap());
+
+        let response = HttpResponse {
+            status: http::StatusCode::NOT_FOUND,
+            headers,
+        };
+
+        let mut buf = Vec::new();
+        response.encode(&mut buf);
+
+        let mut read_buf = buf.as_slice();
+        let decoded = HttpResponse::decode(&mut read_buf).unwrap();
+        assert_eq!(read_buf.len(), 0);
+
+        assert_eq!(decoded.status, http::StatusCode::NOT_FOUND);
+        assert_eq!(decoded.headers.len(), 1);
+        assert_eq!(decoded.headers["content-type"], "text/plain");
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let response = HttpResponse {
+            status: http::StatusCode::OK,
+            headers: http::HeaderMap::new(),
+        };
+
+        let mut buf = Vec::new();
+        response.encode(&mut buf);
+
+        // A response has no method.
+        let err = HttpRequest::decode(&mut buf.as_slice()).unwrap_err();
+        assert!(matches!(err, HttpError::InvalidMethod));
+
+        // Not enough data for the whole frame.
+        let err = HttpResponse::decode(&mut &buf[..buf.len() - 1]).unwrap_err();
+        assert!(matches!(err, HttpError::UnexpectedEnd));
+
+        // Not a HEADERS frame.
+        let mut buf = Vec::new();
+        Frame::DATA.encode(&mut buf);
+        VarInt::from_u32(0).encode(&mut buf);
+        let err = HttpResponse::decode(&mut buf.as_slice()).unwrap_err();
+        assert!(matches!(err, HttpError::UnexpectedFrame(Frame::DATA)));
+    }
+}
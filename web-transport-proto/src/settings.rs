@@ -47,6 +47,13 @@ impl Debug for Setting {
                 write!(f, "WEBTRANSPORT_MAX_SESSIONS_DEPRECATED")
             }
             Setting::WEBTRANSPORT_MAX_SESSIONS => write!(f, "WEBTRANSPORT_MAX_SESSIONS"),
+            Setting::WEBTRANSPORT_INITIAL_MAX_DATA => write!(f, "WEBTRANSPORT_INITIAL_MAX_DATA"),
+            Setting::WEBTRANSPORT_INITIAL_MAX_STREAMS_UNI => {
+                write!(f, "WEBTRANSPORT_INITIAL_MAX_STREAMS_UNI")
+            }
+            Setting::WEBTRANSPORT_INITIAL_MAX_STREAMS_BIDI => {
+                write!(f, "WEBTRANSPORT_INITIAL_MAX_STREAMS_BIDI")
+            }
             x if x.is_grease() => write!(f, "GREASE SETTING [{:x?}]", x.0.into_inner()),
             x => write!(f, "UNKNOWN_SETTING [{:x?}]", x.0.into_inner()),
         }
@@ -78,6 +85,11 @@ settings! {
 
     // New way to enable WebTransport
     WEBTRANSPORT_MAX_SESSIONS = 0xc671706a,
+
+    // Session-level flow control, added in draft 07
+    WEBTRANSPORT_INITIAL_MAX_DATA = 0x2b61,
+    WEBTRANSPORT_INITIAL_MAX_STREAMS_UNI = 0x2b64,
+    WEBTRANSPORT_INITIAL_MAX_STREAMS_BIDI = 0x2b65,
 }
 
 #[derive(Error, Debug, Clone)]
@@ -106,6 +118,11 @@ impl Settings {
             return Err(SettingsError::UnexpectedStreamType(typ));
         }
 
+        Self::decode_frame(buf)
+    }
+
+    /// Decode the SETTINGS frame, after the control stream type has already been read.
+    pub fn decode_frame<B: Buf>(buf: &mut B) -> Result<Self, SettingsError> {
         let (typ, mut data) = Frame::read(buf).map_err(|_| SettingsError::UnexpectedEnd)?;
         if typ != Frame::SETTINGS {
             return Err(SettingsError::UnexpectedFrame(typ));
@@ -153,6 +170,16 @@ impl Settings {
         self.insert(Setting::WEBTRANSPORT_ENABLE_DEPRECATED, VarInt::from_u32(1));
     }
 
+    // Returns true if HTTP/3 datagrams are enabled, either via the RFC 9297 or the deprecated setting.
+    pub fn supports_datagrams(&self) -> bool {
+        let datagram = self
+            .get(&Setting::ENABLE_DATAGRAM)
+            .or(self.get(&Setting::ENABLE_DATAGRAM_DEPRECATED))
+            .map(|v| v.into_inner());
+
+        datagram == Some(1)
+    }
+
     // Returns the maximum number of sessions supported.
     pub fn supports_webtransport(&self) -> u64 {
         // Sent by Chrome 114.0.5735.198 (July 19, 2023)
@@ -165,15 +192,7 @@ impl Settings {
         // Setting(4445614305): 454654587, // grease
 
         // NOTE: The presence of ENABLE_WEBTRANSPORT implies ENABLE_CONNECT is supported.
-
-        let datagram = self
-            .get(&Setting::ENABLE_DATAGRAM)
-            .or(self.get(&Setting::ENABLE_DATAGRAM_DEPRECATED))
-            .map(|v| v.into_inner());
-
-        if datagram != Some(1) {
-            return 0;
-        }
+        // NOTE: HTTP/3 datagrams are checked separately, see supports_datagrams.
 
         // The deprecated (before draft-07) way of enabling WebTransport was to send two parameters.
         // Both would send ENABLE=1 and the server would send MAX_SESSIONS=N to limit the sessions.
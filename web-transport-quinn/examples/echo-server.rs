@@ -55,9 +55,14 @@ async fn main() -> anyhow::Result<()> {
     log::info!("listening on {}", args.addr);
 
     // Accept new connections.
-    while let Some(conn) = server.accept().await {
+    while let Some(incoming) = server.accept().await {
+        // We only negotiate the WebTransport ALPN, so there are no raw QUIC connections.
+        let web_transport_quinn::Incoming::WebTransport(request) = incoming else {
+            continue;
+        };
+
         tokio::spawn(async move {
-            let err = run_conn(conn).await;
+            let err = run_conn(request).await;
             if let Err(err) = err {
                 log::error!("connection failed: {err}")
             }
@@ -0,0 +1,278 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The decision made by an [AdmissionPolicy] for each new connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Start the handshake.
+    Accept,
+
+    /// Reject the connection with a CONNECTION_REFUSED error.
+    Refuse,
+
+    /// Silently drop the connection attempt without sending anything.
+    Ignore,
+
+    /// Send a Retry packet, forcing the client to prove it owns the address.
+    ///
+    /// This is treated as [Admission::Accept] if the address was already validated.
+    Retry,
+}
+
+/// Decides whether to start a handshake for each incoming connection, before any crypto is performed.
+///
+/// Configure it with [crate::ServerBuilder::with_admission].
+/// Policies can be combined using a tuple; the first decision that isn't [Admission::Accept] wins.
+pub trait AdmissionPolicy: Send + Sync {
+    /// Called for each new connection, along with the number of handshakes currently in progress.
+    fn admit(&self, incoming: &quinn::Incoming, pending: usize) -> Admission;
+}
+
+impl<A: AdmissionPolicy, B: AdmissionPolicy> AdmissionPolicy for (A, B) {
+    fn admit(&self, incoming: &quinn::Incoming, pending: usize) -> Admission {
+        match self.0.admit(incoming, pending) {
+            Admission::Accept => self.1.admit(incoming, pending),
+            Admission::Retry if incoming.remote_address_validated() => {
+                self.1.admit(incoming, pending)
+            }
+            decision => decision,
+        }
+    }
+}
+
+/// Refuses connections from an IP address once it exceeds a rate, using a token bucket per IP.
+///
+/// At most 10,000 addresses are tracked at once; the least recently used is forgotten to make room for a new one.
+///
+/// Unvalidated source addresses can be spoofed, letting an attacker churn through buckets and reset the limit for others.
+/// Combine this with [RetryWhenBusy], e.g. `(RetryWhenBusy::new(64), RateLimit::new(10.0, 20))`,
+/// so that addresses are validated before they're rate limited while under load.
+pub struct RateLimit {
+    rate: f64,
+    burst: f64,
+
+    // The time it takes for an empty bucket to refill, after which it can be forgotten.
+    idle: Duration,
+
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    lookup: HashMap<IpAddr, Bucket>,
+
+    // The addresses keyed by when they were last used, used to evict the least recently used.
+    order: BTreeMap<u64, IpAddr>,
+
+    // Incremented on every use.
+    sequence: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+
+    // The key of this bucket in `order`.
+    used: u64,
+}
+
+impl RateLimit {
+    // The least recently used bucket is evicted once we're tracking this many addresses.
+    const MAX_BUCKETS: usize = 10_000;
+
+    /// Allow `burst` connections at once from each IP, refilled at `rate` connections per second.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let burst = burst as f64;
+
+        Self {
+            rate,
+            burst,
+            idle: Duration::try_from_secs_f64(burst / rate).unwrap_or(Duration::MAX),
+            buckets: Default::default(),
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated = now;
+    }
+
+    fn admit_ip(&self, ip: IpAddr, now: Instant) -> Admission {
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets {
+            lookup,
+            order,
+            sequence,
+        } = &mut *buckets;
+
+        if !lookup.contains_key(&ip) {
+            // Forget the least recently used addresses if their bucket has refilled or we need to make room.
+            // Every use refills the bucket, so this is also the bucket that has been idle the longest.
+            while let Some(entry) = order.first_entry() {
+                let idle = now.saturating_duration_since(lookup[entry.get()].updated) >= self.idle;
+                if !idle && lookup.len() < Self::MAX_BUCKETS {
+                    break;
+                }
+
+                lookup.remove(&entry.remove());
+            }
+        }
+
+        let bucket = lookup.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+            used: *sequence,
+        });
+
+        // Move the address to the back of the order.
+        order.remove(&bucket.used);
+        bucket.used = *sequence;
+        order.insert(bucket.used, ip);
+        *sequence += 1;
+
+        self.refill(bucket, now);
+
+        if bucket.tokens < 1.0 {
+            return Admission::Refuse;
+        }
+
+        bucket.tokens -= 1.0;
+        Admission::Accept
+    }
+}
+
+impl AdmissionPolicy for RateLimit {
+    fn admit(&self, incoming: &quinn::Incoming, _pending: usize) -> Admission {
+        self.admit_ip(incoming.remote_address().ip(), Instant::now())
+    }
+}
+
+/// Sends a Retry to unvalidated addresses once more than `threshold` handshakes are pending.
+///
+/// This costs the client a round trip but prevents spoofed addresses from consuming handshakes.
+pub struct RetryWhenBusy {
+    threshold: usize,
+}
+
+impl RetryWhenBusy {
+    pub fn new(threshold: usize) -> Self {
+        Self { threshold }
+    }
+}
+
+impl AdmissionPolicy for RetryWhenBusy {
+    fn admit(&self, _incoming: &quinn::Incoming, pending: usize) -> Admission {
+        match pending > self.threshold {
+            true => Admission::Retry,
+            false => Admission::Accept,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(n: u32) -> IpAddr {
+        IpAddr::from(n.to_be_bytes())
+    }
+
+    #[test]
+    fn test_rate_limit_burst() {
+        let limit = RateLimit::new(1.0, 3);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limit.admit_ip(ip(1), now), Admission::Accept);
+        }
+        assert_eq!(limit.admit_ip(ip(1), now), Admission::Refuse);
+
+        // Other addresses have their own bucket.
+        assert_eq!(limit.admit_ip(ip(2), now), Admission::Accept);
+    }
+
+    #[test]
+    fn test_rate_limit_refill() {
+        let limit = RateLimit::new(2.0, 1);
+        let now = Instant::now();
+
+        assert_eq!(limit.admit_ip(ip(1), now), Admission::Accept);
+        assert_eq!(limit.admit_ip(ip(1), now), Admission::Refuse);
+
+        let later = now + Duration::from_millis(250);
+        assert_eq!(limit.admit_ip(ip(1), later), Admission::Refuse);
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limit.admit_ip(ip(1), later), Admission::Accept);
+        assert_eq!(limit.admit_ip(ip(1), later), Admission::Refuse);
+    }
+
+    #[test]
+    fn test_rate_limit_expire_idle() {
+        let limit = RateLimit::new(1.0, 2);
+        let now = Instant::now();
+
+        limit.admit_ip(ip(1), now);
+        limit.admit_ip(ip(2), now + Duration::from_secs(1));
+
+        // The first bucket has refilled by now, so it's forgotten when a new address shows up.
+        limit.admit_ip(ip(3), now + Duration::from_secs(2));
+
+        let buckets = limit.buckets.lock().unwrap();
+        assert!(!buckets.lookup.contains_key(&ip(1)));
+        assert!(buckets.lookup.contains_key(&ip(2)));
+        assert_eq!(
+            buckets.order.values().copied().collect::<Vec<_>>(),
+            [ip(2), ip(3)]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_max_buckets() {
+        // Buckets never refill, so only the cap evicts them.
+        let limit = RateLimit::new(0.0, 1);
+        let now = Instant::now();
+
+        for n in 0..RateLimit::MAX_BUCKETS as u32 + 10 {
+            assert_eq!(limit.admit_ip(ip(n), now), Admission::Accept);
+        }
+
+        {
+            let buckets = limit.buckets.lock().unwrap();
+            assert_eq!(buckets.lookup.len(), RateLimit::MAX_BUCKETS);
+            assert_eq!(buckets.order.len(), RateLimit::MAX_BUCKETS);
+            assert_eq!(
+                buckets.order.first_key_value().map(|(_, ip)| *ip),
+                Some(ip(10))
+            );
+        }
+
+        // A tracked address is still limited, while an evicted one starts over.
+        assert_eq!(limit.admit_ip(ip(10), now), Admission::Refuse);
+        assert_eq!(limit.admit_ip(ip(0), now), Admission::Accept);
+    }
+
+    #[test]
+    fn test_rate_limit_evict_lru() {
+        let limit = RateLimit::new(0.0, 2);
+        let now = Instant::now();
+
+        for n in 0..RateLimit::MAX_BUCKETS as u32 {
+            limit.admit_ip(ip(n), now);
+        }
+
+        // Using the first address again keeps it from being evicted by a new one.
+        assert_eq!(limit.admit_ip(ip(0), now), Admission::Accept);
+        limit.admit_ip(ip(u32::MAX), now);
+
+        let buckets = limit.buckets.lock().unwrap();
+        assert!(buckets.lookup.contains_key(&ip(0)));
+        assert!(!buckets.lookup.contains_key(&ip(1)));
+        assert_eq!(buckets.lookup.len(), buckets.order.len());
+    }
+}
@@ -5,7 +5,7 @@ use crate::crypto;
 use tokio::net::lookup_host;
 use url::{Host, Url};
 
-use crate::{ClientError, Session, ALPN};
+use crate::{ClientError, Limits, Qlog, Session, ALPN};
 use quinn::{crypto::rustls::QuicClientConfig, rustls};
 use rustls::{client::danger::ServerCertVerifier, pki_types::CertificateDer};
 
@@ -24,6 +24,10 @@ pub struct ClientBuilder {
     provider: crypto::Provider,
     congestion_controller:
         Option<Arc<dyn quinn::congestion::ControllerFactory + Send + Sync + 'static>>,
+    key_log: Option<Arc<dyn rustls::KeyLog>>,
+    limits: Limits,
+    #[cfg(feature = "qlog")]
+    qlog: Option<std::path::PathBuf>,
 }
 
 impl ClientBuilder {
@@ -32,6 +36,10 @@ impl ClientBuilder {
         Self {
             provider: crypto::default_provider(),
             congestion_controller: None,
+            key_log: None,
+            limits: Limits::default(),
+            #[cfg(feature = "qlog")]
+            qlog: None,
         }
     }
 
@@ -60,6 +68,39 @@ impl ClientBuilder {
         self
     }
 
+    /// Limit how much data the peer can make us buffer. See [Limits].
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// Log TLS secrets using the provided [rustls::KeyLog], allowing packet captures to be decrypted.
+    ///
+    /// This should only be used for debugging; anybody with access to the secrets can decrypt the traffic.
+    pub fn with_key_log(self, key_log: Arc<dyn rustls::KeyLog>) -> Self {
+        Self {
+            key_log: Some(key_log),
+            ..self
+        }
+    }
+
+    /// Log TLS secrets to the file specified by the `SSLKEYLOGFILE` environment variable, if set.
+    ///
+    /// The format is understood by Wireshark. See [Self::with_key_log].
+    pub fn with_key_log_file(self) -> Self {
+        self.with_key_log(Arc::new(rustls::KeyLogFile::new()))
+    }
+
+    /// Write a qlog file for each connection into the specified directory.
+    ///
+    /// The files include QUIC events as well as HTTP/3 and WebTransport events, and can be viewed with qvis.
+    #[cfg(feature = "qlog")]
+    pub fn with_qlog(self, dir: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            qlog: Some(dir.into()),
+            ..self
+        }
+    }
+
     /// Accept any certificate from the server if it uses a known root CA.
     pub fn with_system_roots(self) -> Result<Client, ClientError> {
         let mut roots = rustls::RootCertStore::empty();
@@ -146,20 +187,31 @@ impl ClientBuilder {
     fn build(self, mut crypto: rustls::ClientConfig) -> Result<Client, ClientError> {
         crypto.alpn_protocols = vec![ALPN.as_bytes().to_vec()];
 
-        let client_config = QuicClientConfig::try_from(crypto).unwrap();
-        let mut client_config = quinn::ClientConfig::new(Arc::new(client_config));
-
-        let mut transport = quinn::TransportConfig::default();
-        if let Some(cc) = &self.congestion_controller {
-            transport.congestion_controller_factory(cc.clone());
+        if let Some(key_log) = self.key_log.clone() {
+            crypto.key_log = key_log;
         }
 
-        client_config.transport_config(transport.into());
+        let crypto = Arc::new(crypto);
+        let transport = Arc::new(transport_config(
+            self.congestion_controller.clone(),
+            &self.limits,
+        ));
+        let client_config = quic_config(&crypto, &transport);
 
         let client = quinn::Endpoint::client("[::]:0".parse().unwrap()).unwrap();
         Ok(Client {
             endpoint: client,
             config: client_config,
+            custom: Some((crypto, transport)),
+            limits: self.limits.clone(),
+            #[cfg(feature = "qlog")]
+            qlog: self.qlog.map(|path| {
+                let cc = self.congestion_controller;
+                let limits = self.limits.clone();
+                crate::QlogDir::new(path, "client", move || {
+                    transport_config(cc.clone(), &limits)
+                })
+            }),
         })
     }
 }
@@ -174,18 +226,69 @@ impl Default for ClientBuilder {
 pub struct Client {
     endpoint: quinn::Endpoint,
     config: quinn::ClientConfig,
+
+    // Used to build a config with a different ALPN, which is only possible when created by ClientBuilder.
+    custom: Option<(Arc<rustls::ClientConfig>, Arc<quinn::TransportConfig>)>,
+
+    limits: Limits,
+    #[cfg(feature = "qlog")]
+    qlog: Option<crate::QlogDir>,
 }
 
 impl Client {
     /// Manually create a client via a Quinn endpoint and config.
     ///
     /// The ALPN MUST be set to [ALPN].
+    /// [Self::connect_raw] is not supported, since the ALPN can't be changed without the TLS config.
     pub fn new(endpoint: quinn::Endpoint, config: quinn::ClientConfig) -> Self {
-        Self { endpoint, config }
+        Self {
+            endpoint,
+            config,
+            custom: None,
+            limits: Limits::default(),
+            #[cfg(feature = "qlog")]
+            qlog: None,
+        }
     }
 
     /// Connect to the server.
     pub async fn connect(&self, url: Url) -> Result<Session, ClientError> {
+        let (host, remote) = Self::resolve(&url).await?;
+        let (config, qlog) = self.config(&self.config, remote);
+
+        // Connect to the server using the addr we just resolved.
+        let conn = self.endpoint.connect_with(config, remote, &host)?;
+        let conn = conn.await?;
+
+        // Connect with the connection we established.
+        Session::connect_with(conn, url, qlog, self.limits.clone()).await
+    }
+
+    /// Connect to the server using raw QUIC with the given ALPN, skipping the WebTransport handshake.
+    ///
+    /// The connection is wrapped with [Session::raw], so it can be used like a WebTransport session.
+    /// The server must support the ALPN, see [crate::ServerBuilder::with_alpns].
+    pub async fn connect_raw(&self, url: Url, alpn: &[u8]) -> Result<Session, ClientError> {
+        let (crypto, transport) = self
+            .custom
+            .as_ref()
+            .ok_or(ClientError::CustomAlpnUnsupported)?;
+
+        let mut crypto = rustls::ClientConfig::clone(crypto);
+        crypto.alpn_protocols = vec![alpn.to_vec()];
+        let config = quic_config(&Arc::new(crypto), transport);
+
+        let (host, remote) = Self::resolve(&url).await?;
+        let (config, _qlog) = self.config(&config, remote);
+
+        let conn = self.endpoint.connect_with(config, remote, &host)?;
+        let conn = conn.await?;
+
+        Ok(Session::raw(conn, url))
+    }
+
+    // Returns the host name and the address to connect to.
+    async fn resolve(url: &Url) -> Result<(String, SocketAddr), ClientError> {
         let port = url.port().unwrap_or(443);
 
         // TODO error on username:password in host
@@ -213,14 +316,29 @@ impl Client {
             Host::Ipv6(ipv6) => (ipv6.to_string(), SocketAddr::new(IpAddr::V6(ipv6), port)),
         };
 
-        // Connect to the server using the addr we just resolved.
-        let conn = self
-            .endpoint
-            .connect_with(self.config.clone(), remote, &host)?;
-        let conn = conn.await?;
+        Ok((host, remote))
+    }
 
-        // Connect with the connection we established.
-        Session::connect(conn, url).await
+    // Returns the config for a new connection, creating a qlog file if enabled.
+    #[cfg_attr(not(feature = "qlog"), allow(unused_variables))]
+    fn config(
+        &self,
+        config: &quinn::ClientConfig,
+        remote: SocketAddr,
+    ) -> (quinn::ClientConfig, Qlog) {
+        #[cfg(feature = "qlog")]
+        if let Some(dir) = &self.qlog {
+            match dir.create(remote) {
+                Ok((qlog, transport)) => {
+                    let mut config = config.clone();
+                    config.transport_config(Arc::new(transport));
+                    return (config, qlog);
+                }
+                Err(err) => log::warn!("failed to create qlog file: {err}"),
+            }
+        }
+
+        (config.clone(), Qlog::default())
     }
 }
 
@@ -230,6 +348,33 @@ impl Default for Client {
     }
 }
 
+// Returns the QUIC config for the given TLS config.
+fn quic_config(
+    crypto: &Arc<rustls::ClientConfig>,
+    transport: &Arc<quinn::TransportConfig>,
+) -> quinn::ClientConfig {
+    let crypto = QuicClientConfig::try_from(crypto.clone()).unwrap();
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+    config.transport_config(transport.clone());
+    config
+}
+
+// Returns the transport config shared by clients and servers.
+pub(crate) fn transport_config(
+    congestion_controller: Option<
+        Arc<dyn quinn::congestion::ControllerFactory + Send + Sync + 'static>,
+    >,
+    limits: &Limits,
+) -> quinn::TransportConfig {
+    let mut transport = quinn::TransportConfig::default();
+    transport.receive_window(limits.receive_window());
+    if let Some(cc) = congestion_controller {
+        transport.congestion_controller_factory(cc);
+    }
+
+    transport
+}
+
 #[derive(Debug)]
 struct ServerFingerprints {
     provider: crypto::Provider,
@@ -1,10 +1,14 @@
-use std::io;
+use std::{future::poll_fn, io, sync::Arc};
 
-use web_transport_proto::{ConnectRequest, ConnectResponse, VarInt};
+use bytes::Bytes;
+use futures::{stream::FuturesUnordered, StreamExt};
+use web_transport_proto::{ConnectRequest, ConnectResponse, Frame, VarInt};
 
 use thiserror::Error;
 use url::Url;
 
+use crate::{serve_http, HttpHandler, Limits, Owner, PendingBi, PendingSet, Qlog, SessionError};
+
 #[derive(Error, Debug, Clone)]
 pub enum ConnectError {
     #[error("quic stream was closed early")]
@@ -24,6 +28,42 @@ pub enum ConnectError {
 
     #[error("http error status: {0}")]
     ErrorStatus(http::StatusCode),
+
+    #[error("headers are too large")]
+    TooLarge,
+}
+
+// Bidirectional streams and datagrams that arrived before the CONNECT request.
+struct EarlyStreams {
+    // Streams still reading their frame type, since the CONNECT stream might not be first.
+    pending: PendingSet<PendingBi>,
+
+    // Streams that will be handed off to the session.
+    streams: Vec<PendingBi>,
+
+    // The number of WebTransport streams buffered in `streams`.
+    buffered: usize,
+
+    // QUIC datagrams, still prefixed with the session ID.
+    datagrams: Vec<Bytes>,
+}
+
+impl EarlyStreams {
+    fn new() -> Self {
+        Self {
+            pending: PendingSet::new(),
+            streams: Vec::new(),
+            buffered: 0,
+            datagrams: Vec::new(),
+        }
+    }
+
+    // Includes any streams we haven't finished reading the type for, in the order they were opened.
+    fn into_streams(mut self) -> Vec<PendingBi> {
+        self.streams.extend(self.pending.into_streams());
+        self.streams.sort_by_key(PendingBi::id);
+        self.streams
+    }
 }
 
 pub struct Connect {
@@ -35,52 +75,324 @@ pub struct Connect {
 
     #[allow(dead_code)]
     recv: quinn::RecvStream,
+
+    // Used to record the CONNECT exchange and anything that happens on the stream afterwards.
+    qlog: Qlog,
+
+    // Also used to limit capsules after the CONNECT exchange.
+    limits: Limits,
+
+    // Any other bidirectional streams that arrived before the CONNECT request, taken by the session.
+    streams: Vec<PendingBi>,
+
+    // Any QUIC datagrams that arrived before the CONNECT request, taken by the session.
+    datagrams: Vec<Bytes>,
+
+    // Any data after the HEADERS frame, such as capsules sent before the response.
+    remaining: Bytes,
 }
 
 impl Connect {
-    pub async fn accept(conn: &quinn::Connection) -> Result<Self, ConnectError> {
-        // Accept the stream that will be used to send the HTTP CONNECT request.
-        // If they try to send any other type of HTTP request, we will error out.
-        let (send, mut recv) = conn.accept_bi().await?;
-        let mut buf = Vec::new();
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "connect", skip_all, fields(stream_id, url), err)
+    )]
+    pub async fn accept(
+        conn: &quinn::Connection,
+        qlog: Qlog,
+        limits: Limits,
+        handler: Option<&Arc<dyn HttpHandler>>,
+    ) -> Result<Self, ConnectError> {
+        let mut early = EarlyStreams::new();
+
+        // Requests still reading their HEADERS frame, so a slow request doesn't delay the CONNECT request.
+        let mut requests = FuturesUnordered::new();
 
-        // Read the request from the client, buffering more data until we get a full response.
         loop {
-            // Read more data into the buffer.
-            // We use the chunk API here instead of read_buf literally just to return a quinn::ReadError instead of io::Error.
-            let chunk = recv.read_chunk(usize::MAX, true).await?;
-            let chunk = chunk.ok_or(ConnectError::UnexpectedEnd)?;
-            buf.extend_from_slice(&chunk.bytes); // TODO avoid copying on the first loop.
+            // Accept the stream that will be used to send the HTTP CONNECT request, identified by the HEADERS frame.
+            // If they try to send any other type of HTTP request, we will error out unless there's a handler for it.
+            let (mut send, mut recv, res) = tokio::select! {
+                res = Self::accept_stream(conn, &qlog, &limits, &mut early) => {
+                    let (mut send, mut recv) = res?;
+
+                    if requests.len() >= limits.max_pending_streams {
+                        log::debug!("too many pending requests: id={}", send.id());
+                        Self::reject(&mut send, &mut recv, web_transport_proto::H3_EXCESSIVE_LOAD);
+                        continue;
+                    }
+
+                    // Read the request from the client, buffering more data until we get a full HEADERS frame.
+                    let (conn, limits) = (conn.clone(), limits.clone());
+                    requests.push(async move {
+                        let headers = Self::read_headers(&conn, &mut recv, &limits);
+                        let res = tokio::time::timeout(limits.stream_header_timeout, headers).await;
+                        (send, recv, res)
+                    });
 
-            // Create a cursor that will tell us how much of the buffer was read.
-            let mut limit = io::Cursor::new(&buf);
+                    continue;
+                }
+                Some(res) = requests.next() => res,
+            };
 
-            // Try to decode the request.
-            let request = match ConnectRequest::decode(&mut limit) {
-                // It worked, return it.
-                Ok(req) => req,
+            let Ok(buf) = res else {
+                log::debug!("timed out reading HTTP request: id={}", send.id());
+                Self::reject(
+                    &mut send,
+                    &mut recv,
+                    web_transport_proto::H3_REQUEST_INCOMPLETE,
+                );
+                continue;
+            };
+            let buf = buf?;
 
-                // We didn't have enough data in the buffer, so we'll read more and try again.
-                Err(web_transport_proto::ConnectError::UnexpectedEnd) => {
-                    log::debug!("buffering CONNECT request");
+            let mut cursor = io::Cursor::new(&buf);
+            let request = match ConnectRequest::decode(&mut cursor) {
+                Ok(request) => request,
+
+                // A plain HTTP/3 request, served while we wait for the CONNECT request.
+                Err(web_transport_proto::ConnectError::WrongMethod(Some(method)))
+                    if method != http::Method::CONNECT && handler.is_some() =>
+                {
+                    Self::serve_request(handler.unwrap(), &buf, send, recv);
                     continue;
                 }
 
                 // Some other fatal error.
-                Err(e) => return Err(e.into()),
+                Err(e) => return Err(Self::malformed(conn, e)),
             };
 
             log::debug!("received CONNECT request: {request:?}");
+            qlog.connect_request(Owner::Remote, send.id(), &request.url);
+            #[cfg(feature = "tracing")]
+            tracing::Span::current()
+                .record("stream_id", tracing::field::display(send.id()))
+                .record("url", tracing::field::display(&request.url));
 
             // The request was successfully decoded, so we can send a response.
+            let position = cursor.position() as usize;
+            let remaining = Bytes::from(buf).slice(position..);
+            let datagrams = std::mem::take(&mut early.datagrams);
+
+            // Keep serving any requests that were still being read.
+            if let (Some(handler), false) = (handler, requests.is_empty()) {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    while let Some((mut send, mut recv, res)) = requests.next().await {
+                        match res {
+                            Ok(Ok(buf)) => Self::serve_request(&handler, &buf, send, recv),
+                            _ => Self::reject(
+                                &mut send,
+                                &mut recv,
+                                web_transport_proto::H3_REQUEST_INCOMPLETE,
+                            ),
+                        }
+                    }
+                });
+            }
+
             return Ok(Self {
                 request,
                 send,
                 recv,
+                qlog,
+                limits,
+                streams: early.into_streams(),
+                datagrams,
+                remaining,
             });
         }
     }
 
+    // Serve plain HTTP/3 requests until the connection is closed, used when the peer doesn't support WebTransport.
+    pub async fn serve(
+        conn: &quinn::Connection,
+        qlog: &Qlog,
+        limits: &Limits,
+        handler: &Arc<dyn HttpHandler>,
+    ) -> Result<(), ConnectError> {
+        let mut early = EarlyStreams::new();
+
+        // There's no session to hand streams or datagrams off to, so don't buffer any.
+        // WebTransport streams are reset with WT_BUFFERED_STREAM_REJECTED instead.
+        let limits = &Limits {
+            max_buffered_streams: 0,
+            max_buffered_datagrams: 0,
+            ..limits.clone()
+        };
+
+        loop {
+            let (mut send, mut recv) = Self::accept_stream(conn, qlog, limits, &mut early).await?;
+
+            // Ignore streams with an unknown type, like a session would.
+            early.streams.clear();
+
+            let conn = conn.clone();
+            let limits = limits.clone();
+            let handler = handler.clone();
+
+            // Read each request in its own task, so a slow request doesn't block the others.
+            tokio::spawn(async move {
+                let headers = Self::read_headers(&conn, &mut recv, &limits);
+                let buf = match tokio::time::timeout(limits.stream_header_timeout, headers).await {
+                    Ok(Ok(buf)) => buf,
+                    Ok(Err(err)) => {
+                        log::debug!("failed to read HTTP request: {err}");
+                        Self::reject(
+                            &mut send,
+                            &mut recv,
+                            web_transport_proto::H3_REQUEST_INCOMPLETE,
+                        );
+                        return;
+                    }
+                    Err(_) => {
+                        log::debug!("timed out reading HTTP request");
+                        Self::reject(
+                            &mut send,
+                            &mut recv,
+                            web_transport_proto::H3_REQUEST_INCOMPLETE,
+                        );
+                        return;
+                    }
+                };
+
+                Self::serve_request(&handler, &buf, send, recv);
+            });
+        }
+    }
+
+    // Pass a plain HTTP/3 request to the handler, rejecting CONNECT requests and malformed ones.
+    fn serve_request(
+        handler: &Arc<dyn HttpHandler>,
+        buf: &[u8],
+        mut send: quinn::SendStream,
+        mut recv: quinn::RecvStream,
+    ) {
+        match ConnectRequest::decode(&mut io::Cursor::new(buf)) {
+            Err(web_transport_proto::ConnectError::WrongMethod(Some(method)))
+                if method != http::Method::CONNECT => {}
+            // There's no session waiting for this CONNECT request, either because the peer didn't enable WebTransport or it's a duplicate.
+            Ok(request) => {
+                log::debug!("rejecting CONNECT request: {request:?}");
+                return Self::reject(
+                    &mut send,
+                    &mut recv,
+                    web_transport_proto::H3_REQUEST_REJECTED,
+                );
+            }
+            Err(err) => {
+                log::debug!("malformed HTTP request: {err}");
+                return Self::reject(&mut send, &mut recv, web_transport_proto::H3_MESSAGE_ERROR);
+            }
+        }
+
+        match web_transport_proto::HttpRequest::decode(&mut io::Cursor::new(buf)) {
+            Ok(request) => serve_http(handler, request, send, recv),
+            Err(err) => {
+                log::debug!("malformed HTTP request: {err}");
+                Self::reject(&mut send, &mut recv, web_transport_proto::H3_MESSAGE_ERROR);
+            }
+        }
+    }
+
+    // Accept bidirectional streams until we find one starting with a HEADERS frame.
+    // WebTransport streams and datagrams may arrive first, so they're buffered until the session is established.
+    async fn accept_stream(
+        conn: &quinn::Connection,
+        qlog: &Qlog,
+        limits: &Limits,
+        early: &mut EarlyStreams,
+    ) -> Result<(quinn::SendStream, quinn::RecvStream), ConnectError> {
+        loop {
+            tokio::select! {
+                res = conn.accept_bi() => {
+                    let (mut send, mut recv) = res?;
+
+                    // Don't let the peer make us hold an unbounded number of streams.
+                    if early.pending.len() + early.streams.len() >= limits.max_pending_streams {
+                        log::debug!("too many pending bidirectional streams: id={}", send.id());
+                        Self::reject(&mut send, &mut recv, web_transport_proto::H3_EXCESSIVE_LOAD);
+                        continue;
+                    }
+
+                    let deadline = tokio::time::Instant::now() + limits.stream_header_timeout;
+                    early.pending.insert(PendingBi::new(send, recv, deadline));
+                }
+                (stream, typ) = poll_fn(|cx| early.pending.poll_next(cx, |stream, cx| stream.poll_type(cx, qlog))) => {
+                    let typ = match typ {
+                        Ok(typ) => typ,
+                        Err(SessionError::ConnectionError(err)) => return Err(err.into()),
+                        Err(err) => {
+                            let (mut send, mut recv) = stream.into_stream();
+                            log::debug!("rejecting bidirectional stream: id={} err={err}", send.id());
+                            Self::reject(&mut send, &mut recv, web_transport_proto::H3_REQUEST_INCOMPLETE);
+                            continue;
+                        }
+                    };
+
+                    match Frame(typ) {
+                        Frame::HEADERS => return Ok(stream.into_stream()),
+                        Frame::WEBTRANSPORT if early.buffered >= limits.max_buffered_streams => {
+                            log::debug!("too many buffered bidirectional streams");
+                            let (mut send, mut recv) = stream.into_stream();
+                            Self::reject(&mut send, &mut recv, web_transport_proto::WT_BUFFERED_STREAM_REJECTED);
+                        }
+                        Frame::WEBTRANSPORT => {
+                            log::debug!("buffering bidirectional stream: id={}", stream.id());
+                            early.buffered += 1;
+                            early.streams.push(stream);
+                        }
+                        // Let the session ignore it.
+                        _ => early.streams.push(stream),
+                    }
+                }
+                res = conn.read_datagram() => {
+                    let datagram = res?;
+
+                    // Datagrams are unreliable, so drop any that don't fit.
+                    if early.datagrams.len() >= limits.max_buffered_datagrams {
+                        log::debug!("too many buffered datagrams");
+                        continue;
+                    }
+
+                    early.datagrams.push(datagram);
+                }
+            }
+        }
+    }
+
+    // Read until the buffer contains a full HEADERS frame.
+    async fn read_headers(
+        conn: &quinn::Connection,
+        recv: &mut quinn::RecvStream,
+        limits: &Limits,
+    ) -> Result<Vec<u8>, ConnectError> {
+        // We already read the frame type, so add it back to the buffer.
+        let mut buf = Vec::new();
+        Frame::HEADERS.encode(&mut buf);
+
+        loop {
+            // Read more data into the buffer.
+            // We use the chunk API here instead of read_buf literally just to return a quinn::ReadError instead of io::Error.
+            let chunk = recv.read_chunk(usize::MAX, true).await?;
+            let chunk = chunk.ok_or(ConnectError::UnexpectedEnd)?;
+            buf.extend_from_slice(&chunk.bytes); // TODO avoid copying on the first loop.
+            if Frame::read(&mut io::Cursor::new(&buf)).is_ok() {
+                return Ok(buf);
+            }
+
+            // We didn't have enough data in the buffer, so we'll read more and try again.
+            Self::check_size(conn, &buf, limits)?;
+            log::debug!("buffering HEADERS frame");
+        }
+    }
+
+    // Reset both sides of a stream with an HTTP/3 error code.
+    fn reject(send: &mut quinn::SendStream, recv: &mut quinn::RecvStream, code: u64) {
+        // Ignore the error if the stream is already closed.
+        send.reset(code.try_into().unwrap()).ok();
+        recv.stop(code.try_into().unwrap()).ok();
+    }
+
     // Called by the server to send a response to the client.
     pub async fn respond(&mut self, status: http::StatusCode) -> Result<(), quinn::WriteError> {
         let resp = ConnectResponse { status };
@@ -91,14 +403,35 @@ impl Connect {
         resp.encode(&mut buf);
 
         self.send.write_all(&buf).await?;
+        self.qlog
+            .connect_response(Owner::Local, self.send.id(), status);
 
         Ok(())
     }
 
-    pub async fn open(conn: &quinn::Connection, url: Url) -> Result<Self, ConnectError> {
+    // Finish the stream and wait until the peer has received everything, so the response isn't lost when the connection is dropped.
+    pub async fn finish(&mut self) {
+        // Ignore the errors, since the peer may have already closed the connection.
+        self.send.finish().ok();
+        self.send.stopped().await.ok();
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "connect", skip_all, fields(stream_id, url = %url), err)
+    )]
+    pub async fn open(
+        conn: &quinn::Connection,
+        url: Url,
+        qlog: Qlog,
+        limits: Limits,
+    ) -> Result<Self, ConnectError> {
         // Create a new stream that will be used to send the CONNECT frame.
         let (mut send, mut recv) = conn.open_bi().await?;
 
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("stream_id", tracing::field::display(send.id()));
+
         // Create a new CONNECT request that we'll send using HTTP/3
         let request = ConnectRequest { url };
 
@@ -108,6 +441,7 @@ impl Connect {
         let mut buf = Vec::new();
         request.encode(&mut buf);
         send.write_all(&buf).await?;
+        qlog.connect_request(Owner::Local, send.id(), &request.url);
 
         buf.clear();
 
@@ -129,29 +463,65 @@ impl Connect {
 
                 // We didn't have enough data in the buffer, so we'll read more and try again.
                 Err(web_transport_proto::ConnectError::UnexpectedEnd) => {
+                    Self::check_size(conn, &buf, &limits)?;
                     log::debug!("buffering CONNECT response");
                     continue;
                 }
 
                 // Some other fatal error.
-                Err(e) => return Err(e.into()),
+                Err(e) => return Err(Self::malformed(conn, e)),
             };
 
             log::debug!("received CONNECT response: {res:?}");
+            qlog.connect_response(Owner::Remote, send.id(), res.status);
 
             // Throw an error if we didn't get a 200 OK.
             if res.status != http::StatusCode::OK {
                 return Err(ConnectError::ErrorStatus(res.status));
             }
 
+            let position = limit.position() as usize;
+            let remaining = Bytes::from(buf).slice(position..);
+
             return Ok(Self {
                 request,
                 send,
                 recv,
+                qlog,
+                limits,
+                streams: Vec::new(),
+                datagrams: Vec::new(),
+                remaining,
             });
         }
     }
 
+    // Close the connection if the peer is trying to make us buffer too much.
+    // Only called while the frame is incomplete, so any data after it doesn't count.
+    fn check_size(
+        conn: &quinn::Connection,
+        buf: &[u8],
+        limits: &Limits,
+    ) -> Result<(), ConnectError> {
+        // Leave some room for the frame header.
+        if buf.len() as u64 <= limits.max_field_section_size.saturating_add(16) {
+            return Ok(());
+        }
+
+        let code = web_transport_proto::H3_EXCESSIVE_LOAD.try_into().unwrap();
+        conn.close(code, b"headers too large");
+
+        Err(ConnectError::TooLarge)
+    }
+
+    // Close the connection because the CONNECT request/response was malformed.
+    fn malformed(conn: &quinn::Connection, err: web_transport_proto::ConnectError) -> ConnectError {
+        let code = web_transport_proto::H3_MESSAGE_ERROR.try_into().unwrap();
+        conn.close(code, b"malformed CONNECT");
+
+        err.into()
+    }
+
     // The session ID is the stream ID of the CONNECT request.
     pub fn session_id(&self) -> VarInt {
         // We gotta convert from the Quinn VarInt to the (forked) WebTransport VarInt.
@@ -165,7 +535,26 @@ impl Connect {
         &self.request.url
     }
 
-    pub(super) fn into_inner(self) -> (quinn::SendStream, quinn::RecvStream) {
-        (self.send, self.recv)
+    pub fn qlog(&self) -> &Qlog {
+        &self.qlog
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // Take the streams that arrived before the CONNECT request.
+    pub fn take_streams(&mut self) -> Vec<PendingBi> {
+        std::mem::take(&mut self.streams)
+    }
+
+    // Take the QUIC datagrams that arrived before the CONNECT request.
+    pub fn take_datagrams(&mut self) -> Vec<Bytes> {
+        std::mem::take(&mut self.datagrams)
+    }
+
+    // Returns the streams along with any data read after the HEADERS frame.
+    pub(super) fn into_inner(self) -> (quinn::SendStream, quinn::RecvStream, Bytes) {
+        (self.send, self.recv, self.remaining)
     }
 }
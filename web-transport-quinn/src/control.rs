@@ -0,0 +1,314 @@
+use std::io::Cursor;
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::sync::watch;
+use web_transport_proto::{Frame, VarInt};
+
+use crate::Limits;
+
+// A connection error, closing the connection with an HTTP/3 error code.
+#[derive(Debug)]
+struct ControlError {
+    code: u64,
+    reason: &'static str,
+}
+
+impl ControlError {
+    fn new(code: u64, reason: &'static str) -> Self {
+        Self { code, reason }
+    }
+}
+
+// Reads the peer's control stream after SETTINGS, enforcing the RFC 9114 rules for the lifetime of the connection.
+pub(crate) struct Control {
+    conn: quinn::Connection,
+    recv: quinn::RecvStream,
+
+    // Decodes the frames read from the stream.
+    frames: ControlFrames,
+
+    // The ID from the most recent GOAWAY frame.
+    goaway: watch::Sender<Option<VarInt>>,
+}
+
+impl Control {
+    pub fn new(
+        conn: quinn::Connection,
+        recv: quinn::RecvStream,
+        remaining: Bytes,
+        limits: Limits,
+    ) -> (Self, watch::Receiver<Option<VarInt>>) {
+        let (goaway, goaway_rx) = watch::channel(None);
+
+        let control = Self {
+            conn,
+            recv,
+            frames: ControlFrames::new(remaining, limits),
+            goaway,
+        };
+
+        (control, goaway_rx)
+    }
+
+    pub async fn run(mut self) {
+        if let Err(err) = self.run_inner().await {
+            log::warn!(
+                "control stream error: code={:#x} reason={}",
+                err.code,
+                err.reason
+            );
+
+            self.conn
+                .close(err.code.try_into().unwrap(), err.reason.as_bytes());
+        }
+    }
+
+    async fn run_inner(&mut self) -> Result<(), ControlError> {
+        loop {
+            self.frames.decode()?;
+
+            // Only notify the session when the GOAWAY ID changes.
+            let goaway = self.frames.goaway;
+            self.goaway.send_if_modified(|prev| {
+                let modified = *prev != goaway;
+                *prev = goaway;
+                modified
+            });
+
+            let chunk = match self.recv.read_chunk(usize::MAX, true).await {
+                Ok(Some(chunk)) => chunk.bytes,
+                // The connection is already closed, so there's nothing to do.
+                Err(quinn::ReadError::ConnectionLost(_)) => return Ok(()),
+                Ok(None) | Err(_) => {
+                    return Err(ControlError::new(
+                        web_transport_proto::H3_CLOSED_CRITICAL_STREAM,
+                        "control stream closed",
+                    ))
+                }
+            };
+
+            self.frames.push(chunk);
+        }
+    }
+}
+
+// Decodes the frames on the control stream, independent of the stream itself.
+struct ControlFrames {
+    limits: Limits,
+
+    // Data read after the SETTINGS frame.
+    buf: BytesMut,
+
+    // The number of bytes left to skip for a frame we don't care about.
+    skip: u64,
+
+    // The ID from the most recent GOAWAY frame.
+    goaway: Option<VarInt>,
+}
+
+impl ControlFrames {
+    fn new(remaining: Bytes, limits: Limits) -> Self {
+        Self {
+            limits,
+            buf: BytesMut::from(remaining),
+            skip: 0,
+            goaway: None,
+        }
+    }
+
+    // Add data read from the stream.
+    fn push(&mut self, mut chunk: Bytes) {
+        // Discard the payload of any frames we're skipping without buffering it.
+        let skip = self.skip.min(chunk.len() as u64);
+        chunk.advance(skip as usize);
+        self.skip -= skip;
+
+        self.buf.extend_from_slice(&chunk);
+    }
+
+    // Decode every complete frame in the buffer.
+    fn decode(&mut self) -> Result<(), ControlError> {
+        while self.decode_frame()? {}
+
+        // Don't let the peer make us buffer forever.
+        if self.buf.len() > self.limits.max_settings_size {
+            return Err(ControlError::new(
+                web_transport_proto::H3_EXCESSIVE_LOAD,
+                "control frame too large",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Decode the next frame in the buffer, returning false if more data is needed.
+    fn decode_frame(&mut self) -> Result<bool, ControlError> {
+        if self.skip > 0 {
+            let skip = self.skip.min(self.buf.len() as u64);
+            self.buf.advance(skip as usize);
+            self.skip -= skip;
+
+            if self.skip > 0 {
+                return Ok(false);
+            }
+        }
+
+        let mut cursor = Cursor::new(&self.buf[..]);
+        let (Ok(typ), Ok(size)) = (Frame::decode(&mut cursor), VarInt::decode(&mut cursor)) else {
+            return Ok(false);
+        };
+
+        let header = cursor.position() as usize;
+        let size = size.into_inner();
+
+        match typ {
+            Frame::SETTINGS => Err(ControlError::new(
+                web_transport_proto::H3_FRAME_UNEXPECTED,
+                "duplicate SETTINGS",
+            )),
+            Frame::DATA | Frame::HEADERS | Frame::PUSH_PROMISE => Err(ControlError::new(
+                web_transport_proto::H3_FRAME_UNEXPECTED,
+                "unexpected frame on control stream",
+            )),
+            // Reserved because they were used by HTTP/2.
+            Frame(t) if matches!(t.into_inner(), 0x02 | 0x06 | 0x08 | 0x09) => Err(
+                ControlError::new(web_transport_proto::H3_FRAME_UNEXPECTED, "HTTP/2 frame"),
+            ),
+            Frame::GOAWAY => {
+                // The payload is a single VarInt.
+                if size > 8 {
+                    return Err(ControlError::new(
+                        web_transport_proto::H3_FRAME_ERROR,
+                        "invalid GOAWAY",
+                    ));
+                }
+
+                if self.buf.len() < header + size as usize {
+                    return Ok(false);
+                }
+
+                let mut payload = &self.buf[header..header + size as usize];
+                let id = VarInt::decode(&mut payload)
+                    .ok()
+                    .filter(|_| payload.is_empty())
+                    .ok_or(ControlError::new(
+                        web_transport_proto::H3_FRAME_ERROR,
+                        "invalid GOAWAY",
+                    ))?;
+
+                self.goaway(id)?;
+                self.buf.advance(header + size as usize);
+
+                Ok(true)
+            }
+            // Skip any unknown frames, including CANCEL_PUSH and MAX_PUSH_ID because we don't support push.
+            _ => {
+                self.buf.advance(header);
+                self.skip = size;
+
+                Ok(true)
+            }
+        }
+    }
+
+    fn goaway(&mut self, id: VarInt) -> Result<(), ControlError> {
+        log::debug!("received GOAWAY: id={id}");
+
+        // The ID may only decrease.
+        if let Some(prev) = self.goaway {
+            if id > prev {
+                return Err(ControlError::new(
+                    web_transport_proto::H3_ID_ERROR,
+                    "GOAWAY ID increased",
+                ));
+            }
+        }
+
+        self.goaway = Some(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(typ: Frame, payload: &[u8]) -> Bytes {
+        let mut buf = Vec::new();
+        typ.encode(&mut buf);
+        VarInt::from_u32(payload.len() as u32).encode(&mut buf);
+        buf.extend_from_slice(payload);
+        buf.into()
+    }
+
+    fn goaway(id: u32) -> Bytes {
+        let mut payload = Vec::new();
+        VarInt::from_u32(id).encode(&mut payload);
+        frame(Frame::GOAWAY, &payload)
+    }
+
+    fn decode(chunks: &[Bytes]) -> Result<ControlFrames, ControlError> {
+        let mut frames = ControlFrames::new(Bytes::new(), Limits::default());
+        for chunk in chunks {
+            frames.push(chunk.clone());
+            frames.decode()?;
+        }
+
+        Ok(frames)
+    }
+
+    #[test]
+    fn test_goaway() {
+        let frames = decode(&[goaway(8), goaway(4)]).unwrap();
+        assert_eq!(frames.goaway, Some(VarInt::from_u32(4)));
+        assert!(frames.buf.is_empty());
+    }
+
+    #[test]
+    fn test_goaway_split() {
+        let data = goaway(1024);
+        let frames = decode(&[data.slice(..2), data.slice(2..)]).unwrap();
+        assert_eq!(frames.goaway, Some(VarInt::from_u32(1024)));
+    }
+
+    #[test]
+    fn test_goaway_increased() {
+        let err = decode(&[goaway(4), goaway(8)]).err().unwrap();
+        assert_eq!(err.code, web_transport_proto::H3_ID_ERROR);
+    }
+
+    #[test]
+    fn test_goaway_invalid() {
+        let err = decode(&[frame(Frame::GOAWAY, &[0x04, 0x00])])
+            .err()
+            .unwrap();
+        assert_eq!(err.code, web_transport_proto::H3_FRAME_ERROR);
+    }
+
+    #[test]
+    fn test_duplicate_settings() {
+        let err = decode(&[frame(Frame::SETTINGS, &[])]).err().unwrap();
+        assert_eq!(err.code, web_transport_proto::H3_FRAME_UNEXPECTED);
+    }
+
+    #[test]
+    fn test_unexpected_frame() {
+        for typ in [Frame::DATA, Frame::HEADERS, Frame(VarInt::from_u32(0x06))] {
+            let err = decode(&[frame(typ, &[])]).err().unwrap();
+            assert_eq!(err.code, web_transport_proto::H3_FRAME_UNEXPECTED);
+        }
+    }
+
+    #[test]
+    fn test_skip_unknown() {
+        // The payload is larger than the buffer limit, but it's skipped instead of buffered.
+        let payload = vec![0; Limits::default().max_settings_size * 2];
+        let data = frame(Frame(VarInt::from_u32(0x21)), &payload);
+        let (first, second) = (data.slice(..100), data.slice(100..));
+
+        let frames = decode(&[first, second, goaway(4)]).unwrap();
+        assert_eq!(frames.goaway, Some(VarInt::from_u32(4)));
+        assert_eq!(frames.skip, 0);
+        assert!(frames.buf.is_empty());
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bytes::Bytes;
+use tokio::sync::Notify;
+
+use crate::Limits;
+
+// The number of outgoing DATAGRAM capsules that can be queued before we start dropping them.
+const MAX_QUEUED_DATAGRAMS: usize = 64;
+
+/// Which datagram to drop when a queue is full. See [`crate::Session::set_datagram_drop_policy`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DatagramDropPolicy {
+    /// Drop the oldest queued datagram to make room, favoring fresh data. This is what Quinn does.
+    #[default]
+    DropOldest,
+
+    /// Drop the new datagram, favoring data that was queued first.
+    DropNewest,
+}
+
+// Datagram state shared between Session clones.
+//
+// This includes datagrams sent as DATAGRAM capsules on the CONNECT stream, see RFC 9297 Section 3.5.
+// These are reliable and ordered, but they work when QUIC datagrams are disabled or too small for the path.
+pub(crate) struct Datagrams {
+    // False for raw QUIC sessions, which don't have a CONNECT stream.
+    capsules: bool,
+
+    // Send datagrams as capsules when they can't be sent as QUIC datagrams.
+    fallback: AtomicBool,
+
+    // Drop new datagrams instead of old ones when a queue is full.
+    drop_newest: AtomicBool,
+
+    // Datagrams to be written to the CONNECT stream.
+    pub outgoing: Arc<DatagramQueue>,
+
+    // Datagrams read from the CONNECT stream.
+    pub incoming: Arc<DatagramQueue>,
+}
+
+// The other half of [Datagrams], owned by the task reading and writing the CONNECT stream.
+// The queues are closed when this is dropped.
+pub(crate) struct DatagramsTask {
+    pub outgoing: Arc<DatagramQueue>,
+    pub incoming: Arc<DatagramQueue>,
+}
+
+impl Datagrams {
+    pub fn new(limits: &Limits) -> (Self, DatagramsTask) {
+        let outgoing = Arc::new(DatagramQueue::new(MAX_QUEUED_DATAGRAMS));
+        let incoming = Arc::new(DatagramQueue::new(limits.max_datagram_capsules.max(1)));
+
+        let this = Self {
+            capsules: true,
+            fallback: AtomicBool::new(false),
+            drop_newest: AtomicBool::new(false),
+            outgoing: outgoing.clone(),
+            incoming: incoming.clone(),
+        };
+
+        let task = DatagramsTask { outgoing, incoming };
+
+        (this, task)
+    }
+
+    // Datagrams for a raw QUIC connection, which can't use capsules.
+    pub fn raw() -> Self {
+        let (mut this, _task) = Self::new(&Limits::default());
+        this.capsules = false;
+        this
+    }
+
+    pub fn fallback(&self) -> bool {
+        self.fallback.load(Ordering::Relaxed)
+    }
+
+    pub fn set_fallback(&self, enabled: bool) {
+        self.fallback
+            .store(enabled && self.capsules, Ordering::Relaxed);
+    }
+
+    pub fn policy(&self) -> DatagramDropPolicy {
+        match self.drop_newest.load(Ordering::Relaxed) {
+            true => DatagramDropPolicy::DropNewest,
+            false => DatagramDropPolicy::DropOldest,
+        }
+    }
+
+    pub fn set_policy(&self, policy: DatagramDropPolicy) {
+        let drop_newest = policy == DatagramDropPolicy::DropNewest;
+        self.drop_newest.store(drop_newest, Ordering::Relaxed);
+    }
+}
+
+impl Drop for DatagramsTask {
+    fn drop(&mut self) {
+        self.outgoing.close();
+        self.incoming.close();
+    }
+}
+
+// The result of pushing a datagram onto a queue.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Push {
+    Queued,
+
+    // The datagram was queued, but the oldest one was dropped.
+    Evicted,
+
+    // The datagram was dropped.
+    Dropped,
+
+    // The queue is closed.
+    Closed,
+}
+
+// A bounded queue of datagrams that drops instead of blocking when full, unless asked to wait.
+pub(crate) struct DatagramQueue {
+    state: Mutex<QueueState>,
+    capacity: usize,
+
+    // Notified whenever a datagram is pushed or popped, or the queue is closed.
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    queue: VecDeque<Bytes>,
+    closed: bool,
+}
+
+impl DatagramQueue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Default::default(),
+            capacity,
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn push(&self, data: Bytes, policy: DatagramDropPolicy) -> Push {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Push::Closed;
+        }
+
+        let mut res = Push::Queued;
+
+        if state.queue.len() >= self.capacity {
+            match policy {
+                DatagramDropPolicy::DropNewest => return Push::Dropped,
+                DatagramDropPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    res = Push::Evicted;
+                }
+            }
+        }
+
+        state.queue.push_back(data);
+        self.notify.notify_waiters();
+
+        res
+    }
+
+    // Wait until there's room in the queue instead of dropping anything.
+    pub async fn push_wait(&self, data: Bytes) -> Push {
+        loop {
+            // Register before checking the queue so we don't miss a notification.
+            let notified = self.notify.notified();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return Push::Closed;
+                }
+
+                if state.queue.len() < self.capacity {
+                    state.queue.push_back(data);
+                    self.notify.notify_waiters();
+                    return Push::Queued;
+                }
+            }
+
+            notified.await;
+        }
+    }
+
+    // Wait for the next datagram, or None if the queue is closed and empty.
+    pub async fn pop(&self) -> Option<Bytes> {
+        loop {
+            let notified = self.notify.notified();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(data) = state.queue.pop_front() {
+                    self.notify.notify_waiters();
+                    return Some(data);
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            notified.await;
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn data(byte: u8) -> Bytes {
+        Bytes::from(vec![byte])
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let queue = DatagramQueue::new(2);
+        assert_eq!(
+            queue.push(data(1), DatagramDropPolicy::DropOldest),
+            Push::Queued
+        );
+        assert_eq!(
+            queue.push(data(2), DatagramDropPolicy::DropOldest),
+            Push::Queued
+        );
+        assert_eq!(
+            queue.push(data(3), DatagramDropPolicy::DropOldest),
+            Push::Evicted
+        );
+
+        assert_eq!(queue.pop().await, Some(data(2)));
+        assert_eq!(queue.pop().await, Some(data(3)));
+    }
+
+    #[tokio::test]
+    async fn test_drop_newest() {
+        let queue = DatagramQueue::new(2);
+        assert_eq!(
+            queue.push(data(1), DatagramDropPolicy::DropNewest),
+            Push::Queued
+        );
+        assert_eq!(
+            queue.push(data(2), DatagramDropPolicy::DropNewest),
+            Push::Queued
+        );
+        assert_eq!(
+            queue.push(data(3), DatagramDropPolicy::DropNewest),
+            Push::Dropped
+        );
+
+        assert_eq!(queue.pop().await, Some(data(1)));
+        assert_eq!(queue.pop().await, Some(data(2)));
+    }
+
+    #[tokio::test]
+    async fn test_push_wait() {
+        let queue = Arc::new(DatagramQueue::new(1));
+        assert_eq!(queue.push_wait(data(1)).await, Push::Queued);
+
+        // The queue is full, so the second push waits for a pop instead of dropping anything.
+        let push = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.push_wait(data(2)).await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!push.is_finished());
+
+        assert_eq!(queue.pop().await, Some(data(1)));
+        assert_eq!(push.await.unwrap(), Push::Queued);
+        assert_eq!(queue.pop().await, Some(data(2)));
+    }
+
+    #[tokio::test]
+    async fn test_closed() {
+        let queue = Arc::new(DatagramQueue::new(1));
+        assert_eq!(
+            queue.push(data(1), DatagramDropPolicy::DropOldest),
+            Push::Queued
+        );
+
+        // Closing wakes a blocked push.
+        let push = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.push_wait(data(2)).await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.close();
+        assert_eq!(push.await.unwrap(), Push::Closed);
+
+        // Queued datagrams are still delivered after the queue is closed.
+        assert_eq!(
+            queue.push(data(3), DatagramDropPolicy::DropOldest),
+            Push::Closed
+        );
+        assert_eq!(queue.pop().await, Some(data(1)));
+        assert_eq!(queue.pop().await, None);
+    }
+}
@@ -34,6 +34,9 @@ pub enum ClientError {
 
     #[error("rustls error: {0}")]
     Rustls(#[from] rustls::Error),
+
+    #[error("custom ALPNs require a Client created by ClientBuilder")]
+    CustomAlpnUnsupported,
 }
 
 /// An errors returned by [`crate::Session`], split based on if they are underlying QUIC errors or WebTransport errors.
@@ -46,7 +49,7 @@ pub enum SessionError {
     WebTransportError(#[from] WebTransportError),
 
     #[error("send datagram error: {0}")]
-    SendDatagramError(#[from] quinn::SendDatagramError),
+    SendDatagramError(#[from] SendDatagramError),
 }
 
 /// An error that can occur when reading/writing the WebTransport stream header.
@@ -58,6 +61,9 @@ pub enum WebTransportError {
     #[error("unknown session")]
     UnknownSession,
 
+    #[error("timed out reading stream header")]
+    HeaderTimeout,
+
     #[error("read error: {0}")]
     ReadError(#[from] quinn::ReadExactError),
 
@@ -79,6 +85,9 @@ pub enum WriteError {
 
     #[error("stream closed")]
     ClosedStream,
+
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
 }
 
 impl From<quinn::WriteError> for WriteError {
@@ -208,34 +217,41 @@ pub enum ServerError {
 
     #[error("rustls error: {0}")]
     Rustls(#[from] rustls::Error),
+
+    #[error("handshake timeout")]
+    HandshakeTimeout,
+
+    #[error("too many pending handshakes")]
+    TooManyHandshakes,
+
+    #[error("connection not admitted")]
+    NotAdmitted,
 }
 
-// #[derive(Clone, Error, Debug)]
-// pub enum SendDatagramError {
-//     #[error("Unsupported peer")]
-//     UnsupportedPeer,
+/// An error returned by [`crate::Session::send_datagram`]. Similar to [`quinn::SendDatagramError`].
+#[derive(Clone, Error, Debug)]
+pub enum SendDatagramError {
+    /// The datagram is larger than [`crate::Session::max_datagram_size`], which is included.
+    #[error("datagram too large: max={0}")]
+    TooLarge(usize),
 
-//     #[error("Datagram support Disabled by peer")]
-//     DatagramSupportDisabled,
+    /// The peer didn't enable HTTP/3 or QUIC datagrams.
+    #[error("datagrams not supported by peer")]
+    UnsupportedByPeer,
 
-//     #[error("Datagram Too large")]
-//     TooLarge,
+    /// QUIC datagrams are disabled locally, see [`quinn::TransportConfig::datagram_receive_buffer_size`].
+    #[error("datagrams disabled")]
+    Disabled,
 
-//     #[error("Session errorr: {0}")]
-//     SessionError(#[from] SessionError),
-// }
+    /// The buffer passed to [`crate::Session::send_datagram_with_headroom`] is shorter than the headroom, which is included.
+    #[error("missing datagram headroom: size={0}")]
+    MissingHeadroom(usize),
 
-// impl From<quinn::SendDatagramError> for SendDatagramError {
-//     fn from(value: quinn::SendDatagramError) -> Self {
-//          match value {
-//              quinn::SendDatagramError::UnsupportedByPeer => SendDatagramError::UnsupportedPeer,
-//              quinn::SendDatagramError::Disabled => SendDatagramError::DatagramSupportDisabled,
-//              quinn::SendDatagramError::TooLarge => SendDatagramError::TooLarge,
-//              quinn::SendDatagramError::ConnectionLost(e) => SendDatagramError::SessionError(e.into()),
-//          }
-//     }
-// }
+    #[error("session closed: {0}")]
+    SessionClosed(#[from] quinn::ConnectionError),
+}
 
 impl web_transport_trait::Error for SessionError {}
+impl web_transport_trait::Error for SendDatagramError {}
 impl web_transport_trait::Error for WriteError {}
 impl web_transport_trait::Error for ReadError {}
@@ -0,0 +1,620 @@
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use tokio::sync::mpsc;
+use web_transport_proto::{Capsule, Setting};
+
+use crate::Limits;
+
+// The largest value allowed for a stream limit, see RFC 9000 Section 4.6.
+const MAX_STREAMS: u64 = 1 << 60;
+
+// Session-level flow control, see draft-ietf-webtrans-http3 Section 5.6.
+//
+// This is only enabled if the peer advertises any of the initial limits in SETTINGS.
+// Otherwise the peer doesn't implement it, so we can't expect it to respect our limits either.
+pub(crate) struct FlowControl {
+    state: Mutex<FlowState>,
+
+    // Used to close the connection if the peer exceeds a limit.
+    conn: Option<quinn::Connection>,
+
+    // Capsules that need to be written to the CONNECT stream.
+    capsules: Option<mpsc::UnboundedSender<Capsule>>,
+}
+
+#[derive(Default)]
+struct FlowState {
+    // The limits set by the peer.
+    send: Option<SendCredit>,
+
+    // The limits we advertised.
+    recv: Option<RecvCredit>,
+
+    // Tasks blocked waiting for more send credit.
+    wakers: Vec<Waker>,
+}
+
+struct SendCredit {
+    data: Credit,
+    bi: Credit,
+    uni: Credit,
+}
+
+#[derive(Default)]
+struct Credit {
+    max: u64,
+    used: u64,
+
+    // The limit we last sent a BLOCKED capsule for, so we only send it once.
+    blocked: Option<u64>,
+}
+
+impl Credit {
+    fn new(max: u64) -> Self {
+        Self {
+            max,
+            ..Default::default()
+        }
+    }
+
+    fn available(&self) -> u64 {
+        self.max.saturating_sub(self.used)
+    }
+
+    // Returns true if the limit was increased.
+    fn increase(&mut self, max: u64) -> bool {
+        if max <= self.max {
+            return false;
+        }
+
+        self.max = max;
+        true
+    }
+}
+
+struct RecvCredit {
+    data: Window,
+    bi: Window,
+    uni: Window,
+}
+
+struct Window {
+    // The limit we advertised.
+    max: u64,
+
+    // The amount of credit granted ahead of the released amount.
+    size: u64,
+
+    // The number of streams opened or bytes received by the peer.
+    received: u64,
+
+    // The number of streams closed or bytes read by the application.
+    released: u64,
+}
+
+impl Window {
+    fn new(size: u64) -> Self {
+        Self {
+            max: size,
+            size,
+            received: 0,
+            released: 0,
+        }
+    }
+
+    // Returns false if the peer exceeded the limit.
+    fn receive(&mut self, amount: u64) -> bool {
+        self.received = self.received.saturating_add(amount);
+        self.received <= self.max
+    }
+
+    // Returns the new limit if enough credit was released to be worth advertising.
+    fn release(&mut self, amount: u64) -> Option<u64> {
+        self.released = self.released.saturating_add(amount);
+
+        // Wait until at least half of the window has been used, to avoid sending a capsule for every byte.
+        if self.max.saturating_sub(self.released) > self.size / 2 {
+            return None;
+        }
+
+        let max = self.released.saturating_add(self.size);
+        if max <= self.max {
+            return None;
+        }
+
+        self.max = max;
+        Some(max)
+    }
+}
+
+impl FlowControl {
+    pub fn new(
+        conn: quinn::Connection,
+        limits: &Limits,
+        remote: &web_transport_proto::Settings,
+        capsules: mpsc::UnboundedSender<Capsule>,
+    ) -> Self {
+        let initial = |setting| remote.get(&setting).map(|v| v.into_inner());
+        let data = initial(Setting::WEBTRANSPORT_INITIAL_MAX_DATA);
+        let uni = initial(Setting::WEBTRANSPORT_INITIAL_MAX_STREAMS_UNI);
+        let bi = initial(Setting::WEBTRANSPORT_INITIAL_MAX_STREAMS_BIDI);
+
+        let mut state = FlowState::default();
+
+        if data.is_some() || uni.is_some() || bi.is_some() {
+            // Any limit that wasn't advertised defaults to zero.
+            state.send = Some(SendCredit {
+                data: Credit::new(data.unwrap_or(0)),
+                uni: Credit::new(uni.unwrap_or(0)),
+                bi: Credit::new(bi.unwrap_or(0)),
+            });
+
+            state.recv = Some(RecvCredit {
+                data: Window::new(limits.initial_max_data),
+                uni: Window::new(limits.initial_max_streams_uni),
+                bi: Window::new(limits.initial_max_streams_bi),
+            });
+        }
+
+        Self {
+            state: Mutex::new(state),
+            conn: Some(conn),
+            capsules: Some(capsules),
+        }
+    }
+
+    // Flow control for a raw QUIC connection, which never blocks.
+    pub fn disabled() -> Self {
+        Self {
+            state: Default::default(),
+            conn: None,
+            capsules: None,
+        }
+    }
+
+    // Wait until the peer allows us to open another stream.
+    // The credit is returned when the guard is dropped, unless the stream was actually opened.
+    pub fn poll_open(&self, cx: &mut Context<'_>, bi: bool) -> Poll<OpenCredit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(send) = &mut state.send else {
+            return Poll::Ready(OpenCredit { flow: None, bi });
+        };
+
+        let credit = match bi {
+            true => &mut send.bi,
+            false => &mut send.uni,
+        };
+
+        if credit.available() > 0 {
+            credit.used += 1;
+            return Poll::Ready(OpenCredit {
+                flow: Some(self),
+                bi,
+            });
+        }
+
+        if credit.blocked != Some(credit.max) {
+            credit.blocked = Some(credit.max);
+            self.send_capsule(Capsule::WtStreamsBlocked {
+                bidi: bi,
+                max: credit.max,
+            });
+        }
+
+        state.register(cx);
+        Poll::Pending
+    }
+
+    // Wait until the peer allows us to send more data, returning up to `size` bytes of credit.
+    pub fn poll_send(&self, cx: &mut Context<'_>, size: usize) -> Poll<usize> {
+        let mut state = self.state.lock().unwrap();
+        let Some(send) = &mut state.send else {
+            return Poll::Ready(size);
+        };
+
+        let credit = &mut send.data;
+        let available = credit.available().min(size as u64);
+
+        if available > 0 || size == 0 {
+            credit.used += available;
+            return Poll::Ready(available as usize);
+        }
+
+        if credit.blocked != Some(credit.max) {
+            credit.blocked = Some(credit.max);
+            self.send_capsule(Capsule::WtDataBlocked { max: credit.max });
+        }
+
+        state.register(cx);
+        Poll::Pending
+    }
+
+    // Return stream credit that was acquired but not used.
+    fn unopen(&self, bi: bool) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(send) = &mut state.send {
+            let credit = match bi {
+                true => &mut send.bi,
+                false => &mut send.uni,
+            };
+
+            credit.used = credit.used.saturating_sub(1);
+            state.wake();
+        }
+    }
+
+    // Return credit that was acquired but not used.
+    pub fn unsend(&self, size: usize) {
+        if size == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(send) = &mut state.send {
+            send.data.used = send.data.used.saturating_sub(size as u64);
+            state.wake();
+        }
+    }
+
+    // The session data credit used so far.
+    #[cfg(test)]
+    pub fn data_sent(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.send.as_ref().map_or(0, |send| send.data.used)
+    }
+
+    // Process a flow control capsule from the peer.
+    pub fn recv_capsule(&self, capsule: &Capsule) {
+        let mut state = self.state.lock().unwrap();
+        let Some(send) = &mut state.send else {
+            return;
+        };
+
+        let increased = match *capsule {
+            Capsule::WtMaxData { max } => send.data.increase(max),
+            Capsule::WtMaxStreams { max, .. } if max > MAX_STREAMS => {
+                drop(state);
+                self.violation("stream limit too large");
+                return;
+            }
+            Capsule::WtMaxStreams { bidi: true, max } => send.bi.increase(max),
+            Capsule::WtMaxStreams { bidi: false, max } => send.uni.increase(max),
+            Capsule::WtDataBlocked { max } => {
+                log::debug!("peer is blocked by the session data limit: max={max}");
+                false
+            }
+            Capsule::WtStreamsBlocked { bidi, max } => {
+                log::debug!("peer is blocked by the session stream limit: bidi={bidi} max={max}");
+                false
+            }
+            _ => false,
+        };
+
+        if increased {
+            state.wake();
+        }
+    }
+
+    // Called when the peer opens a stream, returning false if it exceeded the limit.
+    pub fn recv_stream(&self, bi: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(recv) = &mut state.recv else {
+            return true;
+        };
+
+        let window = match bi {
+            true => &mut recv.bi,
+            false => &mut recv.uni,
+        };
+
+        if window.receive(1) {
+            return true;
+        }
+
+        drop(state);
+        self.violation("too many streams");
+        false
+    }
+
+    // Called when a stream opened by the peer is closed, granting more credit.
+    pub fn close_stream(&self, bi: bool) {
+        let mut state = self.state.lock().unwrap();
+        let Some(recv) = &mut state.recv else {
+            return;
+        };
+
+        let window = match bi {
+            true => &mut recv.bi,
+            false => &mut recv.uni,
+        };
+
+        if let Some(max) = window.release(1) {
+            self.send_capsule(Capsule::WtMaxStreams { bidi: bi, max });
+        }
+    }
+
+    // Called when the application reads data from any stream, granting more credit.
+    pub fn recv_data(&self, size: usize) {
+        if size == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let Some(recv) = &mut state.recv else {
+            return;
+        };
+
+        // Quinn doesn't tell us when data arrives, only when it's read, so we can't detect a peer that exceeds the limit.
+        // Instead the limit is approximated by the QUIC receive window, see Limits::receive_window.
+        if let Some(max) = recv.data.release(size as u64) {
+            self.send_capsule(Capsule::WtMaxData { max });
+        }
+    }
+
+    fn send_capsule(&self, capsule: Capsule) {
+        if let Some(capsules) = &self.capsules {
+            // Ignore the error if the session is closed.
+            capsules.send(capsule).ok();
+        }
+    }
+
+    fn violation(&self, reason: &str) {
+        log::warn!("session flow control error: {reason}");
+
+        if let Some(conn) = &self.conn {
+            let code = web_transport_proto::WT_FLOW_CONTROL_ERROR;
+            conn.close(code.try_into().unwrap(), reason.as_bytes());
+        }
+    }
+}
+
+impl FlowState {
+    fn register(&mut self, cx: &mut Context<'_>) {
+        if !self.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            self.wakers.push(cx.waker().clone());
+        }
+    }
+
+    fn wake(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+// Stream credit acquired by FlowControl::poll_open, returned on drop unless the stream was opened.
+// Otherwise a cancelled or failed open would permanently use up one of the peer's streams.
+pub(crate) struct OpenCredit<'a> {
+    flow: Option<&'a FlowControl>,
+    bi: bool,
+}
+
+impl OpenCredit<'_> {
+    // The stream was opened, so the credit is used for good.
+    pub fn opened(mut self) {
+        self.flow = None;
+    }
+}
+
+impl Drop for OpenCredit<'_> {
+    fn drop(&mut self) {
+        if let Some(flow) = self.flow {
+            flow.unopen(self.bi);
+        }
+    }
+}
+
+// A handle to the session flow control, shared by both halves of a stream.
+// If the peer opened the stream, more stream credit is granted once both halves are dropped.
+pub(crate) struct StreamCredit {
+    flow: Arc<FlowControl>,
+
+    // Set if the peer opened the stream, and whether it's bidirectional.
+    remote: Option<bool>,
+}
+
+impl StreamCredit {
+    pub fn local(flow: Arc<FlowControl>) -> Arc<Self> {
+        Arc::new(Self { flow, remote: None })
+    }
+
+    pub fn remote(flow: Arc<FlowControl>, bi: bool) -> Arc<Self> {
+        Arc::new(Self {
+            flow,
+            remote: Some(bi),
+        })
+    }
+}
+
+impl std::ops::Deref for StreamCredit {
+    type Target = FlowControl;
+
+    fn deref(&self) -> &Self::Target {
+        &self.flow
+    }
+}
+
+impl std::fmt::Debug for StreamCredit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamCredit")
+            .field("remote", &self.remote)
+            .finish()
+    }
+}
+
+impl Drop for StreamCredit {
+    fn drop(&mut self) {
+        if let Some(bi) = self.remote {
+            self.flow.close_stream(bi);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::task::noop_waker_ref;
+
+    use super::*;
+
+    // Flow control with the given send credit (data, uni, bi) and the default receive windows.
+    fn flow(data: u64, uni: u64, bi: u64) -> (FlowControl, mpsc::UnboundedReceiver<Capsule>) {
+        let limits = Limits::default();
+        let state = FlowState {
+            send: Some(SendCredit {
+                data: Credit::new(data),
+                uni: Credit::new(uni),
+                bi: Credit::new(bi),
+            }),
+            recv: Some(RecvCredit {
+                data: Window::new(limits.initial_max_data),
+                uni: Window::new(2),
+                bi: Window::new(2),
+            }),
+            wakers: Vec::new(),
+        };
+
+        let (capsules, rx) = mpsc::unbounded_channel();
+        let flow = FlowControl {
+            state: Mutex::new(state),
+            conn: None,
+            capsules: Some(capsules),
+        };
+
+        (flow, rx)
+    }
+
+    fn cx() -> Context<'static> {
+        Context::from_waker(noop_waker_ref())
+    }
+
+    #[test]
+    fn test_window_release() {
+        let mut window = Window::new(100);
+        assert!(window.receive(100));
+        assert!(!window.receive(1));
+
+        // Nothing is advertised until half of the window has been released.
+        assert_eq!(window.release(49), None);
+        assert_eq!(window.release(1), Some(150));
+        assert_eq!(window.release(1), None);
+
+        // The new limit allows more data.
+        assert!(window.receive(49));
+    }
+
+    #[test]
+    fn test_credit_increase() {
+        let mut credit = Credit::new(10);
+        credit.used = 4;
+        assert_eq!(credit.available(), 6);
+
+        // The limit can only go up.
+        assert!(!credit.increase(5));
+        assert!(credit.increase(20));
+        assert_eq!(credit.available(), 16);
+    }
+
+    #[test]
+    fn test_max_streams_too_large() {
+        let (flow, _rx) = flow(0, 1, 1);
+
+        flow.recv_capsule(&Capsule::WtMaxStreams {
+            bidi: true,
+            max: MAX_STREAMS + 1,
+        });
+
+        let state = flow.state.lock().unwrap();
+        assert_eq!(state.send.as_ref().unwrap().bi.max, 1);
+    }
+
+    #[test]
+    fn test_recv_stream_limit() {
+        let (flow, _rx) = flow(0, 0, 0);
+        assert!(flow.recv_stream(false));
+        assert!(flow.recv_stream(false));
+        assert!(!flow.recv_stream(false));
+
+        // Bidirectional streams have a separate limit.
+        assert!(flow.recv_stream(true));
+    }
+
+    #[test]
+    fn test_close_stream_grants_credit() {
+        let (flow, mut rx) = flow(0, 0, 0);
+        assert!(flow.recv_stream(true));
+
+        flow.close_stream(true);
+        let capsule = rx.try_recv().unwrap();
+        assert!(matches!(
+            capsule,
+            Capsule::WtMaxStreams { bidi: true, max: 3 }
+        ));
+    }
+
+    #[test]
+    fn test_blocked_once() {
+        let (flow, mut rx) = flow(0, 1, 0);
+
+        let Poll::Ready(credit) = flow.poll_open(&mut cx(), false) else {
+            panic!("expected credit");
+        };
+        credit.opened();
+
+        // Only the first blocked poll sends a capsule.
+        assert!(flow.poll_open(&mut cx(), false).is_pending());
+        assert!(flow.poll_open(&mut cx(), false).is_pending());
+        let capsule = rx.try_recv().unwrap();
+        assert!(matches!(
+            capsule,
+            Capsule::WtStreamsBlocked {
+                bidi: false,
+                max: 1
+            }
+        ));
+        assert!(rx.try_recv().is_err());
+
+        // A new limit resets the dedup, so we're blocked again at the new limit.
+        flow.recv_capsule(&Capsule::WtMaxStreams {
+            bidi: false,
+            max: 2,
+        });
+        let Poll::Ready(credit) = flow.poll_open(&mut cx(), false) else {
+            panic!("expected credit");
+        };
+        credit.opened();
+        assert!(flow.poll_open(&mut cx(), false).is_pending());
+        let capsule = rx.try_recv().unwrap();
+        assert!(matches!(
+            capsule,
+            Capsule::WtStreamsBlocked {
+                bidi: false,
+                max: 2
+            }
+        ));
+
+        assert!(flow.poll_send(&mut cx(), 10).is_pending());
+        assert!(flow.poll_send(&mut cx(), 10).is_pending());
+        let capsule = rx.try_recv().unwrap();
+        assert!(matches!(capsule, Capsule::WtDataBlocked { max: 0 }));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_open_cancelled() {
+        let (flow, _rx) = flow(0, 0, 1);
+
+        // Dropping the credit without opening a stream returns it.
+        let credit = flow.poll_open(&mut cx(), true);
+        assert!(credit.is_ready());
+        drop(credit);
+
+        let Poll::Ready(credit) = flow.poll_open(&mut cx(), true) else {
+            panic!("credit was leaked");
+        };
+        credit.opened();
+        assert!(flow.poll_open(&mut cx(), true).is_pending());
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, Weak},
+};
+
+/// A group of streams that share bandwidth fairly with other groups, created by [`crate::Session::send_group`].
+///
+/// This mirrors `sendGroup` in the W3C WebTransport API.
+/// Streams within a group are sent in order of [`crate::SendStream::set_priority`], highest first.
+///
+/// Quinn only supports a flat priority per stream, so each stream's priority is replaced with its rank within the group:
+/// the first stream is sent with priority 0, the second with -1, and so on.
+/// Only one stream per group has priority 0, so each group gets the same share of bandwidth regardless of its size.
+/// Streams with the same priority are ranked in the order they were opened, instead of taking turns.
+/// Note that a group's lower ranked streams only get bandwidth when no group's top stream has data to send.
+///
+/// Streams without a group keep their priority as-is, so one with the default priority of 0 is scheduled like the top stream of a group.
+#[derive(Clone, Debug)]
+pub struct SendGroup {
+    state: Arc<SendGroupState>,
+}
+
+#[derive(Debug, Default)]
+struct SendGroupState {
+    members: Mutex<Members>,
+
+    // Replace each stream's priority with its rank, see above.
+    // This is true for every group except the session's default group.
+    ranked: bool,
+}
+
+// Streams are ranked by descending order, then by the order they were opened.
+type Rank = (Reverse<i32>, u64, quinn::StreamId);
+
+#[derive(Debug, Default)]
+struct Members {
+    // The order of each stream, used to find it in the ranking.
+    orders: HashMap<quinn::StreamId, i32>,
+
+    ranked: BTreeMap<Rank, Member>,
+}
+
+#[derive(Debug)]
+struct Member {
+    // The Quinn priority we last set, so we only update streams whose rank changed.
+    priority: Option<i32>,
+
+    // Used to update the stream's priority when its rank changes, even if it's idle.
+    stream: Weak<Mutex<quinn::SendStream>>,
+}
+
+impl SendGroup {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Arc::new(SendGroupState {
+                ranked: true,
+                ..Default::default()
+            }),
+        }
+    }
+
+    // The group for streams that aren't in any other group, so they keep their own priority.
+    pub(crate) fn unranked() -> Self {
+        Self {
+            state: Default::default(),
+        }
+    }
+
+    fn insert(&self, id: quinn::StreamId, order: i32, stream: &Arc<Mutex<quinn::SendStream>>) {
+        let mut members = self.state.members.lock().unwrap();
+        let key = (Reverse(order), id.index(), id);
+
+        // Only streams ranked after the old or new position can change rank.
+        let mut from = key;
+        let member = match members.orders.insert(id, order) {
+            Some(old) => {
+                let old = (Reverse(old), id.index(), id);
+                from = from.min(old);
+                members.ranked.remove(&old).expect("missing member")
+            }
+            None => Member {
+                priority: None,
+                stream: Arc::downgrade(stream),
+            },
+        };
+        members.ranked.insert(key, member);
+
+        if self.state.ranked {
+            return Self::sync(&mut members, from);
+        }
+
+        // Only this stream's priority changed.
+        let member = members.ranked.get_mut(&key).unwrap();
+        if member.priority != Some(order) {
+            stream.lock().unwrap().set_priority(order).ok();
+            member.priority = Some(order);
+        }
+    }
+
+    fn remove(&self, id: quinn::StreamId) {
+        let mut members = self.state.members.lock().unwrap();
+        let Some(order) = members.orders.remove(&id) else {
+            return;
+        };
+
+        let key = (Reverse(order), id.index(), id);
+        members.ranked.remove(&key);
+
+        if self.state.ranked {
+            Self::sync(&mut members, key);
+        }
+    }
+
+    // Update the Quinn priority of every stream ranked at or after `from` that changed.
+    fn sync(members: &mut Members, from: Rank) {
+        let first = members.ranked.len() - members.ranked.range(from..).count();
+
+        for (rank, member) in (first..).zip(members.ranked.range_mut(from..).map(|(_, m)| m)) {
+            let priority = -i32::try_from(rank).unwrap_or(i32::MAX);
+
+            if member.priority == Some(priority) {
+                continue;
+            }
+
+            // The stream is being dropped, or it's closed and will be removed soon.
+            if let Some(stream) = member.stream.upgrade() {
+                stream.lock().unwrap().set_priority(priority).ok();
+            }
+
+            member.priority = Some(priority);
+        }
+    }
+
+    fn order(&self, id: quinn::StreamId) -> Option<i32> {
+        let members = self.state.members.lock().unwrap();
+        members.orders.get(&id).copied()
+    }
+
+    /// The number of streams currently in the group.
+    pub fn len(&self) -> usize {
+        self.state.members.lock().unwrap().orders.len()
+    }
+
+    /// Returns true if there are no streams in the group.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl PartialEq for SendGroup {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl Eq for SendGroup {}
+
+// A stream's membership in a [SendGroup], removed from the group on drop.
+#[derive(Debug)]
+pub(crate) struct SendGroupMember {
+    pub group: SendGroup,
+    id: quinn::StreamId,
+}
+
+impl SendGroupMember {
+    pub fn new(
+        group: SendGroup,
+        stream: &Arc<Mutex<quinn::SendStream>>,
+        id: quinn::StreamId,
+        order: i32,
+    ) -> Self {
+        group.insert(id, order, stream);
+        Self { group, id }
+    }
+
+    pub fn order(&self) -> Option<i32> {
+        self.group.order(self.id)
+    }
+
+    pub fn set_order(&self, order: i32, stream: &Arc<Mutex<quinn::SendStream>>) {
+        self.group.insert(self.id, order, stream);
+    }
+}
+
+impl Drop for SendGroupMember {
+    fn drop(&mut self) {
+        self.group.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    type Stream = Arc<Mutex<quinn::SendStream>>;
+
+    // Returns both connections too, since the streams are closed when they're dropped.
+    async fn streams(count: usize) -> ((quinn::Connection, quinn::Connection), Vec<Stream>) {
+        let (client, server) = testing::connect(|_| {}).await;
+
+        let mut streams = Vec::new();
+        for _ in 0..count {
+            let send = client.open_uni().await.unwrap();
+            streams.push(Arc::new(Mutex::new(send)));
+        }
+
+        ((client, server), streams)
+    }
+
+    fn join(group: &SendGroup, stream: &Stream, order: i32) -> SendGroupMember {
+        let id = stream.lock().unwrap().id();
+        SendGroupMember::new(group.clone(), stream, id, order)
+    }
+
+    fn priority(stream: &Stream) -> i32 {
+        stream.lock().unwrap().priority().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rank() {
+        let (_conns, streams) = streams(4).await;
+        let group = SendGroup::new();
+
+        let a = join(&group, &streams[0], 10);
+        let _b = join(&group, &streams[1], 100);
+        let _c = join(&group, &streams[2], -5);
+
+        // The priority values don't matter, only their order.
+        assert_eq!(priority(&streams[1]), 0);
+        assert_eq!(priority(&streams[0]), -1);
+        assert_eq!(priority(&streams[2]), -2);
+
+        // Ties are ranked in the order the streams were opened.
+        let _d = join(&group, &streams[3], 10);
+        assert_eq!(priority(&streams[0]), -1);
+        assert_eq!(priority(&streams[3]), -2);
+        assert_eq!(priority(&streams[2]), -3);
+
+        // Changing the order updates every stream, even idle ones.
+        a.set_order(1000, &streams[0]);
+        assert_eq!(priority(&streams[0]), 0);
+        assert_eq!(priority(&streams[1]), -1);
+
+        // Leaving the group promotes the remaining streams.
+        drop(a);
+        assert_eq!(priority(&streams[1]), 0);
+        assert_eq!(priority(&streams[3]), -1);
+        assert_eq!(priority(&streams[2]), -2);
+        assert_eq!(group.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rank_tail() {
+        let (_conns, streams) = streams(3).await;
+        let group = SendGroup::new();
+
+        let _a = join(&group, &streams[0], 0);
+        let _b = join(&group, &streams[1], 0);
+
+        // Streams ranked ahead of a new stream aren't touched, so this sentinel survives.
+        streams[0].lock().unwrap().set_priority(42).unwrap();
+        let _c = join(&group, &streams[2], 0);
+        assert_eq!(priority(&streams[0]), 42);
+        assert_eq!(priority(&streams[2]), -2);
+    }
+
+    #[tokio::test]
+    async fn test_one_per_group() {
+        let (_conns, streams) = streams(4).await;
+        let big = SendGroup::new();
+        let small = SendGroup::new();
+
+        // A group with more streams doesn't get more streams at the top priority.
+        let _members: Vec<_> = streams[..3].iter().map(|s| join(&big, s, 0)).collect();
+        let _small = join(&small, &streams[3], 0);
+
+        let top: Vec<_> = streams.iter().filter(|s| priority(s) == 0).collect();
+        assert_eq!(top.len(), 2);
+        assert!(Arc::ptr_eq(top[0], &streams[0]));
+        assert!(Arc::ptr_eq(top[1], &streams[3]));
+    }
+
+    #[tokio::test]
+    async fn test_default_group() {
+        let (_conns, streams) = streams(2).await;
+
+        // Streams without a group keep their own priority, even once other groups exist.
+        let default = SendGroup::unranked();
+        let _group = SendGroup::new();
+        let a = join(&default, &streams[0], 100);
+        let _b = join(&default, &streams[1], 100);
+        assert_eq!(priority(&streams[0]), 100);
+        assert_eq!(priority(&streams[1]), 100);
+
+        a.set_order(-5, &streams[0]);
+        assert_eq!(priority(&streams[0]), -5);
+        assert_eq!(priority(&streams[1]), 100);
+        assert_eq!(default.len(), 2);
+    }
+}
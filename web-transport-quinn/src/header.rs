@@ -0,0 +1,580 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll, Wake, Waker},
+};
+
+use futures::task::AtomicWaker;
+use slab::Slab;
+use tokio::time::{Instant, Sleep};
+
+use web_transport_proto::{Frame, StreamUni, VarInt};
+
+use crate::{Owner, Qlog, SessionError, WebTransportError};
+
+// Decodes a VarInt directly from the stream, a byte range at a time, without any intermediate buffer.
+#[derive(Default)]
+pub(crate) struct VarIntReader {
+    // 8 bytes is the max size of a varint
+    buf: [u8; 8],
+    len: usize,
+}
+
+impl VarIntReader {
+    pub fn poll_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        recv: &mut quinn::RecvStream,
+    ) -> Poll<Result<VarInt, SessionError>> {
+        loop {
+            // Read the first byte because it includes the length.
+            // 0b00 = 1, 0b01 = 2, 0b10 = 4, 0b11 = 8
+            let size = match self.len {
+                0 => 1,
+                _ => 1 << (self.buf[0] >> 6),
+            };
+
+            if self.len == size {
+                // Reset so the reader can be used for the next VarInt.
+                let mut buf = &self.buf[..size];
+                self.len = 0;
+
+                return Poll::Ready(Ok(VarInt::decode(&mut buf).unwrap()));
+            }
+
+            let n = match ready!(recv.poll_read(cx, &mut self.buf[self.len..size])) {
+                Ok(0) => {
+                    let err = quinn::ReadExactError::FinishedEarly(self.len);
+                    return Poll::Ready(Err(WebTransportError::ReadError(err).into()));
+                }
+                Ok(n) => n,
+                Err(quinn::ReadError::ConnectionLost(err)) => return Poll::Ready(Err(err.into())),
+                Err(err) => {
+                    let err = quinn::ReadExactError::ReadError(err);
+                    return Poll::Ready(Err(WebTransportError::ReadError(err).into()));
+                }
+            };
+
+            self.len += n;
+        }
+    }
+}
+
+// An incoming unidirectional stream that's still reading its header.
+pub(crate) struct PendingUni {
+    recv: quinn::RecvStream,
+
+    // The stream type, once read.
+    typ: Option<VarInt>,
+    reader: VarIntReader,
+    deadline: Instant,
+}
+
+impl PendingUni {
+    pub fn new(recv: quinn::RecvStream, deadline: Instant) -> Self {
+        Self {
+            recv,
+            typ: None,
+            reader: VarIntReader::default(),
+            deadline,
+        }
+    }
+
+    // Returns the stream type once it's been read, which is all we need during the handshake.
+    pub fn poll_type(
+        &mut self,
+        cx: &mut Context<'_>,
+        qlog: &Qlog,
+    ) -> Poll<Result<VarInt, SessionError>> {
+        if let Some(typ) = self.typ {
+            return Poll::Ready(Ok(typ));
+        }
+
+        let typ = ready!(self.reader.poll_read(cx, &mut self.recv))?;
+        qlog.stream_type(Owner::Remote, self.id(), typ);
+        self.typ = Some(typ);
+
+        Poll::Ready(Ok(typ))
+    }
+
+    // Returns the stream type once the header has been read, validating the session ID of WebTransport streams.
+    pub fn poll_header(
+        &mut self,
+        cx: &mut Context<'_>,
+        expected_session: VarInt,
+        qlog: &Qlog,
+    ) -> Poll<Result<StreamUni, SessionError>> {
+        let typ = StreamUni(ready!(self.poll_type(cx, qlog))?);
+        if typ == StreamUni::WEBTRANSPORT {
+            // Read the session_id and validate it
+            let session_id = ready!(self.reader.poll_read(cx, &mut self.recv))?;
+            if session_id != expected_session {
+                return Poll::Ready(Err(WebTransportError::UnknownSession.into()));
+            }
+        }
+
+        // We need to keep a reference to the qpack streams if the endpoint (incorrectly) creates them, so return everything.
+        Poll::Ready(Ok(typ))
+    }
+
+    // The time the header must be read by.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    // Streams buffered during the handshake get a new deadline once the session is established.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = deadline;
+    }
+
+    pub fn id(&self) -> quinn::StreamId {
+        self.recv.id()
+    }
+
+    // The stream type, if it's been read.
+    pub fn typ(&self) -> Option<StreamUni> {
+        self.typ.map(StreamUni)
+    }
+
+    pub fn into_stream(self) -> quinn::RecvStream {
+        self.recv
+    }
+}
+
+// An incoming bidirectional stream that's still reading its header.
+pub(crate) struct PendingBi {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+
+    // The frame type, once read.
+    typ: Option<VarInt>,
+    reader: VarIntReader,
+    deadline: Instant,
+}
+
+impl PendingBi {
+    pub fn new(send: quinn::SendStream, recv: quinn::RecvStream, deadline: Instant) -> Self {
+        Self {
+            send,
+            recv,
+            typ: None,
+            reader: VarIntReader::default(),
+            deadline,
+        }
+    }
+
+    // Returns the frame type once it's been read, which is all we need during the handshake.
+    pub fn poll_type(
+        &mut self,
+        cx: &mut Context<'_>,
+        qlog: &Qlog,
+    ) -> Poll<Result<VarInt, SessionError>> {
+        if let Some(typ) = self.typ {
+            return Poll::Ready(Ok(typ));
+        }
+
+        let typ = ready!(self.reader.poll_read(cx, &mut self.recv))?;
+        qlog.stream_type(Owner::Remote, self.id(), typ);
+        self.typ = Some(typ);
+
+        Poll::Ready(Ok(typ))
+    }
+
+    // Returns true once the header has been read if it's a WebTransport stream for our session.
+    pub fn poll_header(
+        &mut self,
+        cx: &mut Context<'_>,
+        expected_session: VarInt,
+        qlog: &Qlog,
+    ) -> Poll<Result<bool, SessionError>> {
+        let typ = ready!(self.poll_type(cx, qlog))?;
+        if Frame(typ) != Frame::WEBTRANSPORT {
+            log::debug!(
+                "ignoring unknown bidirectional stream: id={} type={typ:?}",
+                self.id()
+            );
+            return Poll::Ready(Ok(false));
+        }
+
+        // Read the session ID and validate it.
+        let session_id = ready!(self.reader.poll_read(cx, &mut self.recv))?;
+        if session_id != expected_session {
+            return Poll::Ready(Err(WebTransportError::UnknownSession.into()));
+        }
+
+        Poll::Ready(Ok(true))
+    }
+
+    // The time the header must be read by.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    // Streams buffered during the handshake get a new deadline once the session is established.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = deadline;
+    }
+
+    pub fn id(&self) -> quinn::StreamId {
+        self.send.id()
+    }
+
+    // The frame type, if it's been read.
+    pub fn typ(&self) -> Option<Frame> {
+        self.typ.map(Frame)
+    }
+
+    pub fn into_stream(self) -> (quinn::SendStream, quinn::RecvStream) {
+        (self.send, self.recv)
+    }
+}
+
+// A stream that's still reading its header.
+pub(crate) trait PendingStream {
+    fn id(&self) -> quinn::StreamId;
+
+    // The time the header must be read by.
+    fn deadline(&self) -> Instant;
+}
+
+impl PendingStream for PendingUni {
+    fn id(&self) -> quinn::StreamId {
+        PendingUni::id(self)
+    }
+
+    fn deadline(&self) -> Instant {
+        PendingUni::deadline(self)
+    }
+}
+
+impl PendingStream for PendingBi {
+    fn id(&self) -> quinn::StreamId {
+        PendingBi::id(self)
+    }
+
+    fn deadline(&self) -> Instant {
+        PendingBi::deadline(self)
+    }
+}
+
+// Streams that are still reading their header, stored in a slab.
+// Like FuturesUnordered, each stream gets its own waker so only the streams with new data are polled.
+pub(crate) struct PendingSet<T> {
+    streams: Slab<PendingEntry<T>>,
+
+    // The keys of streams that were woken since they were last polled.
+    woken: Arc<Woken>,
+
+    // The deadline of each stream in the order they were inserted, which is the order they expire.
+    // The stream ID is used to skip streams that were already removed, since keys are reused.
+    deadlines: VecDeque<(Instant, usize, quinn::StreamId)>,
+
+    // Fires when the earliest deadline expires, allocated the first time it's needed.
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+struct PendingEntry<T> {
+    stream: T,
+    waker: Arc<StreamWaker>,
+}
+
+#[derive(Default)]
+struct Woken {
+    keys: Mutex<VecDeque<usize>>,
+
+    // The task polling the set.
+    waker: AtomicWaker,
+}
+
+struct StreamWaker {
+    key: usize,
+    woken: Arc<Woken>,
+
+    // Set while the key is queued, so it's only queued once per poll.
+    queued: AtomicBool,
+}
+
+impl Wake for StreamWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.woken.keys.lock().unwrap().push_back(self.key);
+            self.woken.waker.wake();
+        }
+    }
+}
+
+impl<T: PendingStream> PendingSet<T> {
+    pub fn new() -> Self {
+        Self {
+            streams: Slab::new(),
+            woken: Default::default(),
+            deadlines: VecDeque::new(),
+            timer: None,
+        }
+    }
+
+    // Add a stream, which must not have an earlier deadline than any stream already in the set.
+    pub fn insert(&mut self, stream: T) {
+        let entry = self.streams.vacant_entry();
+        let key = entry.key();
+
+        self.deadlines
+            .push_back((stream.deadline(), key, stream.id()));
+
+        let waker = Arc::new(StreamWaker {
+            key,
+            woken: self.woken.clone(),
+            queued: AtomicBool::new(false),
+        });
+
+        // Poll the stream the next time the set is polled.
+        waker.wake_by_ref();
+
+        entry.insert(PendingEntry { stream, waker });
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    // Returns the remaining streams, in no particular order.
+    pub fn into_streams(self) -> impl Iterator<Item = T> {
+        self.streams.into_iter().map(|(_, entry)| entry.stream)
+    }
+
+    // Poll the streams that were woken, returning the first one to finish reading its header or time out.
+    pub fn poll_next<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut poll: impl FnMut(&mut T, &mut Context<'_>) -> Poll<Result<R, SessionError>>,
+    ) -> Poll<(T, Result<R, SessionError>)> {
+        self.woken.waker.register(cx.waker());
+
+        if let Some(stream) = self.poll_expired(cx) {
+            return Poll::Ready((stream, Err(WebTransportError::HeaderTimeout.into())));
+        }
+
+        loop {
+            let Some(key) = self.woken.keys.lock().unwrap().pop_front() else {
+                return Poll::Pending;
+            };
+
+            // The stream may have been removed after it was woken.
+            let Some(entry) = self.streams.get_mut(key) else {
+                continue;
+            };
+
+            entry.waker.queued.store(false, Ordering::Release);
+
+            let waker = Waker::from(entry.waker.clone());
+            let mut stream_cx = Context::from_waker(&waker);
+
+            if let Poll::Ready(res) = poll(&mut entry.stream, &mut stream_cx) {
+                let entry = self.streams.remove(key);
+                return Poll::Ready((entry.stream, res));
+            }
+        }
+    }
+
+    // Remove the oldest stream if its deadline has passed.
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> Option<T> {
+        loop {
+            let &(deadline, key, id) = self.deadlines.front()?;
+
+            // Skip streams that were already removed.
+            let removed = match self.streams.get(key) {
+                Some(entry) => entry.stream.id() != id,
+                None => true,
+            };
+
+            if removed {
+                self.deadlines.pop_front();
+                continue;
+            }
+
+            if poll_timer(&mut self.timer, cx, deadline).is_pending() {
+                return None;
+            }
+
+            self.deadlines.pop_front();
+            return Some(self.streams.remove(key).stream);
+        }
+    }
+}
+
+// Reuse the same timer for every deadline, only resetting it when the deadline changes.
+fn poll_timer(
+    timer: &mut Option<Pin<Box<Sleep>>>,
+    cx: &mut Context<'_>,
+    deadline: Instant,
+) -> Poll<()> {
+    let sleep = match timer {
+        Some(sleep) => {
+            if sleep.deadline() != deadline {
+                sleep.as_mut().reset(deadline);
+            }
+            sleep
+        }
+        None => timer.insert(Box::pin(tokio::time::sleep_until(deadline))),
+    };
+
+    sleep.as_mut().poll(cx)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::poll_fn, sync::atomic::AtomicUsize, time::Duration};
+
+    use super::*;
+
+    // A stream whose header is ready once the test says so.
+    struct FakeStream {
+        id: quinn::StreamId,
+        deadline: Instant,
+        ready: bool,
+        polls: usize,
+        waker: Arc<Mutex<Option<Waker>>>,
+    }
+
+    impl FakeStream {
+        fn new(index: u64, deadline: Instant) -> Self {
+            Self {
+                id: quinn::StreamId::new(quinn::Side::Client, quinn::Dir::Uni, index),
+                deadline,
+                ready: false,
+                polls: 0,
+                waker: Default::default(),
+            }
+        }
+
+        fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<u64, SessionError>> {
+            self.polls += 1;
+
+            match self.ready {
+                true => Poll::Ready(Ok(self.id.index())),
+                false => {
+                    *self.waker.lock().unwrap() = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    impl PendingStream for FakeStream {
+        fn id(&self) -> quinn::StreamId {
+            self.id
+        }
+
+        fn deadline(&self) -> Instant {
+            self.deadline
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poll_woken() {
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let mut set = PendingSet::new();
+
+        let wakers: Vec<_> = (0..3)
+            .map(|i| {
+                let stream = FakeStream::new(i, deadline);
+                let waker = stream.waker.clone();
+                set.insert(stream);
+                waker
+            })
+            .collect();
+
+        // Every new stream is polled once.
+        let woken = Arc::new(AtomicUsize::new(0));
+        let waker = futures::task::waker(Arc::new(CountWaker(woken.clone())));
+        let mut cx = Context::from_waker(&waker);
+        assert!(set.poll_next(&mut cx, FakeStream::poll).is_pending());
+
+        let polls: Vec<_> = set.streams.iter().map(|(_, e)| e.stream.polls).collect();
+        assert_eq!(polls, [1, 1, 1]);
+
+        // Polling again without a wakeup doesn't poll any stream.
+        assert!(set.poll_next(&mut cx, FakeStream::poll).is_pending());
+        let polls: Vec<_> = set.streams.iter().map(|(_, e)| e.stream.polls).collect();
+        assert_eq!(polls, [1, 1, 1]);
+
+        // Only the woken stream is polled, and the set's task is woken.
+        let before = woken.load(Ordering::SeqCst);
+        set.streams.get_mut(1).unwrap().stream.ready = true;
+        wakers[1].lock().unwrap().take().unwrap().wake();
+        assert_eq!(woken.load(Ordering::SeqCst), before + 1);
+
+        let (stream, res) = match set.poll_next(&mut cx, FakeStream::poll) {
+            Poll::Ready(res) => res,
+            Poll::Pending => panic!("expected a stream"),
+        };
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(stream.polls, 2);
+        assert_eq!(set.len(), 2);
+
+        let polls: Vec<_> = set.streams.iter().map(|(_, e)| e.stream.polls).collect();
+        assert_eq!(polls, [1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_poll_timeout() {
+        let now = Instant::now();
+        let mut set = PendingSet::new();
+        set.insert(FakeStream::new(0, now + Duration::from_millis(20)));
+        set.insert(FakeStream::new(1, now + Duration::from_millis(40)));
+
+        // Streams time out in the order they were inserted.
+        for index in 0..2 {
+            let (stream, res) = poll_fn(|cx| set.poll_next(cx, FakeStream::poll)).await;
+            assert_eq!(stream.id.index(), index);
+            assert!(matches!(
+                res,
+                Err(SessionError::WebTransportError(
+                    WebTransportError::HeaderTimeout
+                ))
+            ));
+        }
+
+        assert!(Instant::now() >= now + Duration::from_millis(40));
+        assert_eq!(set.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_poll_removed_deadline() {
+        let now = Instant::now();
+        let mut set = PendingSet::new();
+
+        let mut first = FakeStream::new(0, now + Duration::from_millis(10));
+        first.ready = true;
+        set.insert(first);
+        set.insert(FakeStream::new(1, now + Duration::from_millis(30)));
+
+        // The first stream finishes, so its deadline is skipped and its key is reused.
+        let (stream, res) = poll_fn(|cx| set.poll_next(cx, FakeStream::poll)).await;
+        assert_eq!((stream.id.index(), res.unwrap()), (0, 0));
+
+        set.insert(FakeStream::new(2, now + Duration::from_secs(60)));
+
+        let (stream, res) = poll_fn(|cx| set.poll_next(cx, FakeStream::poll)).await;
+        assert_eq!(stream.id.index(), 1);
+        assert!(res.is_err());
+        assert_eq!(set.len(), 1);
+    }
+
+    struct CountWaker(Arc<AtomicUsize>);
+
+    impl futures::task::ArcWake for CountWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
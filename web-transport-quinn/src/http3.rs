@@ -0,0 +1,329 @@
+use std::{future::Future, sync::Arc};
+
+use futures::future::BoxFuture;
+use web_transport_proto::{Frame, VarInt};
+
+/// Handles plain HTTP/3 requests, such as a health check, configured with [crate::ServerBuilder::with_http_handler].
+///
+/// This is implemented for any async function or closure that takes a [HttpRequest] and [HttpResponse].
+/// Each request is handled in its own task.
+pub trait HttpHandler: Send + Sync {
+    /// Called for each request, which should be answered using the [HttpResponse].
+    fn handle(&self, request: HttpRequest, response: HttpResponse) -> BoxFuture<'static, ()>;
+}
+
+impl<F, Fut> HttpHandler for F
+where
+    F: Fn(HttpRequest, HttpResponse) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn handle(&self, request: HttpRequest, response: HttpResponse) -> BoxFuture<'static, ()> {
+        Box::pin(self(request, response))
+    }
+}
+
+/// A plain HTTP/3 request received on the same endpoint as WebTransport.
+///
+/// Only the method, path, and headers are provided; any request body is ignored.
+#[derive(Debug)]
+pub struct HttpRequest {
+    inner: web_transport_proto::HttpRequest,
+}
+
+impl HttpRequest {
+    /// The request method, such as GET.
+    pub fn method(&self) -> &http::Method {
+        &self.inner.method
+    }
+
+    /// The path, without the query.
+    pub fn path(&self) -> &str {
+        match self.inner.path.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.inner.path,
+        }
+    }
+
+    /// The query string, if any.
+    pub fn query(&self) -> Option<&str> {
+        self.inner.path.split_once('?').map(|(_, query)| query)
+    }
+
+    /// The `:authority` pseudo-header, if provided.
+    pub fn authority(&self) -> Option<&str> {
+        self.inner.authority.as_deref()
+    }
+
+    /// The request headers, excluding pseudo-headers.
+    pub fn headers(&self) -> &http::HeaderMap {
+        &self.inner.headers
+    }
+}
+
+/// Used to answer a [HttpRequest] with a status and headers, followed by the body.
+///
+/// The request is reset with `H3_INTERNAL_ERROR` if this is dropped without calling [Self::respond].
+pub struct HttpResponse {
+    stream: Option<(quinn::SendStream, quinn::RecvStream)>,
+}
+
+impl HttpResponse {
+    /// Send the status and headers, returning a [HttpBody] used to write the body.
+    pub async fn respond(
+        mut self,
+        status: http::StatusCode,
+        headers: http::HeaderMap,
+    ) -> Result<HttpBody, quinn::WriteError> {
+        let (mut send, recv) = self.stream.take().unwrap();
+
+        let response = web_transport_proto::HttpResponse { status, headers };
+        log::debug!("sending HTTP response: {response:?}");
+
+        let mut buf = Vec::new();
+        response.encode(&mut buf);
+        send.write_all(&buf).await?;
+
+        Ok(HttpBody { send, recv })
+    }
+}
+
+impl Drop for HttpResponse {
+    fn drop(&mut self) {
+        if let Some((mut send, _)) = self.stream.take() {
+            let code = web_transport_proto::H3_INTERNAL_ERROR;
+            send.reset(code.try_into().unwrap()).ok();
+        }
+    }
+}
+
+/// The body of a HTTP/3 response, written as DATA frames.
+///
+/// The body is complete once [Self::finish] is called or this is dropped.
+pub struct HttpBody {
+    send: quinn::SendStream,
+
+    // Keep the request stream open until the response is done.
+    #[allow(dead_code)]
+    recv: quinn::RecvStream,
+}
+
+impl HttpBody {
+    /// Write part of the body as a DATA frame.
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), quinn::WriteError> {
+        let mut header = Vec::new();
+        Frame::DATA.encode(&mut header);
+        VarInt::try_from(data.len()).unwrap().encode(&mut header);
+
+        self.send.write_all(&header).await?;
+        self.send.write_all(data).await?;
+
+        Ok(())
+    }
+
+    /// Mark the body as complete.
+    pub fn finish(mut self) -> Result<(), quinn::ClosedStream> {
+        self.send.finish()
+    }
+}
+
+// Spawn a task to handle a plain HTTP/3 request.
+pub(crate) fn serve_http(
+    handler: &Arc<dyn HttpHandler>,
+    request: web_transport_proto::HttpRequest,
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+) {
+    log::debug!("received HTTP request: {request:?}");
+
+    let request = HttpRequest { inner: request };
+    let response = HttpResponse {
+        stream: Some((send, recv)),
+    };
+
+    let task = handler.handle(request, response);
+
+    #[cfg(feature = "tracing")]
+    let task = tracing::Instrument::in_current_span(task);
+
+    tokio::spawn(task);
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Buf;
+    use url::Url;
+
+    use super::*;
+    use crate::{testing, Handshake, Incoming, Limits, Qlog, Request, ServerError, SettingsError};
+
+    // Responds with the query string, or a 404 if the path isn't /health.
+    fn handler() -> Arc<dyn HttpHandler> {
+        Arc::new(|request: HttpRequest, response: HttpResponse| async move {
+            let status = match request.path() {
+                "/health" => http::StatusCode::OK,
+                _ => http::StatusCode::NOT_FOUND,
+            };
+
+            let mut body = response
+                .respond(status, http::HeaderMap::new())
+                .await
+                .unwrap();
+            body.write(request.query().unwrap_or_default().as_bytes())
+                .await
+                .unwrap();
+            body.finish().unwrap();
+        })
+    }
+
+    // Perform the handshake for a plain HTTP/3 client, which doesn't enable WebTransport.
+    async fn accept(
+        http: Option<Arc<dyn HttpHandler>>,
+    ) -> (
+        quinn::Connection,
+        quinn::SendStream,
+        Result<Handshake, ServerError>,
+    ) {
+        let (client, server) = testing::connect(|_| {}).await;
+
+        let mut buf = Vec::new();
+        web_transport_proto::Settings::default().encode(&mut buf);
+        let mut control = client.open_uni().await.unwrap();
+        control.write_all(&buf).await.unwrap();
+
+        let res = Request::accept_with(server, Qlog::default(), Limits::default(), http).await;
+        (client, control, res)
+    }
+
+    async fn get(conn: &quinn::Connection, path: &str) -> (http::StatusCode, Vec<u8>) {
+        let request = web_transport_proto::HttpRequest {
+            method: http::Method::GET,
+            authority: Some("localhost".to_string()),
+            path: path.to_string(),
+            headers: http::HeaderMap::new(),
+        };
+
+        let mut buf = Vec::new();
+        request.encode(&mut buf);
+
+        let (mut send, mut recv) = conn.open_bi().await.unwrap();
+        send.write_all(&buf).await.unwrap();
+        send.finish().unwrap();
+
+        let buf = recv.read_to_end(4096).await.unwrap();
+        let mut buf = buf.as_slice();
+        let response = web_transport_proto::HttpResponse::decode(&mut buf).unwrap();
+
+        let mut body = Vec::new();
+        while buf.has_remaining() {
+            let (typ, mut data) = Frame::read(&mut buf).unwrap();
+            assert_eq!(typ, Frame::DATA);
+            body.extend_from_slice(&data.copy_to_bytes(data.remaining()));
+        }
+
+        (response.status, body)
+    }
+
+    #[tokio::test]
+    async fn test_http_only() {
+        // The handshake finishes as soon as we know the client doesn't support WebTransport.
+        let (client, _control, res) = accept(Some(handler())).await;
+        let Ok(Handshake::Http(serve)) = res else {
+            panic!("expected a plain HTTP/3 client");
+        };
+
+        // Requests are served until the connection is closed.
+        tokio::spawn(serve);
+        let (status, body) = get(&client, "/health?ok").await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body, b"ok");
+
+        let (status, body) = get(&client, "/missing").await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+        assert!(body.is_empty());
+
+        // The client can't open a session without enabling WebTransport.
+        let request = web_transport_proto::ConnectRequest {
+            url: Url::parse("https://localhost/").unwrap(),
+        };
+
+        let mut buf = Vec::new();
+        request.encode(&mut buf);
+
+        let (mut send, mut recv) = client.open_bi().await.unwrap();
+        send.write_all(&buf).await.unwrap();
+
+        let code = web_transport_proto::H3_REQUEST_REJECTED;
+        let err = recv.read_to_end(4096).await.unwrap_err();
+        assert_eq!(
+            err,
+            quinn::ReadToEndError::Read(quinn::ReadError::Reset(code.try_into().unwrap()))
+        );
+
+        // There's no session for WebTransport streams, so they're rejected instead of buffered.
+        let mut buf = Vec::new();
+        Frame::WEBTRANSPORT.encode(&mut buf);
+        VarInt::from_u32(0).encode(&mut buf);
+
+        let (mut send, mut recv) = client.open_bi().await.unwrap();
+        send.write_all(&buf).await.unwrap();
+
+        let code = web_transport_proto::WT_BUFFERED_STREAM_REJECTED;
+        let err = recv.read_to_end(4096).await.unwrap_err();
+        assert_eq!(
+            err,
+            quinn::ReadToEndError::Read(quinn::ReadError::Reset(code.try_into().unwrap()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_request() {
+        let (client, server) = testing::connect(|_| {}).await;
+
+        let mut settings = web_transport_proto::Settings::default();
+        settings.enable_webtransport(1);
+
+        let mut buf = Vec::new();
+        settings.encode(&mut buf);
+        let mut control = client.open_uni().await.unwrap();
+        control.write_all(&buf).await.unwrap();
+
+        // A request that never finishes its HEADERS frame.
+        let (mut slow, _slow_recv) = client.open_bi().await.unwrap();
+        let mut buf = Vec::new();
+        Frame::HEADERS.encode(&mut buf);
+        slow.write_all(&buf).await.unwrap();
+
+        let request = web_transport_proto::ConnectRequest {
+            url: Url::parse("https://localhost/").unwrap(),
+        };
+
+        let mut buf = Vec::new();
+        request.encode(&mut buf);
+        let (mut send, _recv) = client.open_bi().await.unwrap();
+        send.write_all(&buf).await.unwrap();
+
+        // The CONNECT request isn't stuck behind the slow request, which would take the full timeout.
+        let limits = Limits::default();
+        let accept = Request::accept_with(server, Qlog::default(), limits, Some(handler()));
+        let handshake = tokio::time::timeout(std::time::Duration::from_secs(1), accept)
+            .await
+            .expect("CONNECT request was blocked")
+            .unwrap();
+        let Handshake::Incoming(Incoming::WebTransport(request)) = handshake else {
+            panic!("expected a CONNECT request");
+        };
+        assert_eq!(request.url().path(), "/");
+    }
+
+    #[tokio::test]
+    async fn test_webtransport_unsupported() {
+        // Without a handler, there's nothing to do with a client that doesn't support WebTransport.
+        let (_client, _control, res) = accept(None).await;
+        assert!(matches!(
+            res,
+            Err(ServerError::SettingsError(
+                SettingsError::WebTransportUnsupported
+            ))
+        ));
+    }
+}
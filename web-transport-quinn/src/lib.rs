@@ -20,29 +20,55 @@
 //! # Limitations
 //! WebTransport is able to be pooled with HTTP/3 and multiple WebTransport sessions.
 //! This crate avoids that complexity, doing the bare minimum to support a single WebTransport session that owns the entire QUIC connection.
-//! If you want to support HTTP/3 on the same host/port, you should use another crate (ex. `h3-webtransport`).
+//! Simple HTTP/3 requests, like a health check, can be served with [ServerBuilder::with_http_handler].
+//! If you want to fully support HTTP/3 on the same host/port, you should use another crate (ex. `h3-webtransport`).
 //! If you want to support multiple WebTransport sessions over the same QUIC connection... you should just dial a new QUIC connection instead.
 
 // External
+mod admission;
 mod client;
+mod datagram;
 mod error;
+mod group;
+mod http3;
+mod limits;
 mod recv;
+mod router;
 mod send;
 mod server;
 mod session;
+mod stats;
 
+pub use admission::*;
 pub use client::*;
+pub use datagram::*;
 pub use error::*;
+pub use group::*;
+pub use http3::*;
+pub use limits::*;
 pub use recv::*;
+pub use router::*;
 pub use send::*;
 pub use server::*;
 pub use session::*;
+pub use stats::*;
 
 // Internal
 mod connect;
+mod control;
+mod flow;
+mod header;
+mod qlog;
 mod settings;
 
+#[cfg(test)]
+mod testing;
+
 use connect::*;
+use control::*;
+use flow::*;
+use header::*;
+use qlog::*;
 use settings::*;
 
 /// The HTTP/3 ALPN is required when negotiating a QUIC connection.
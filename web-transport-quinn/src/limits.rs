@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+/// Limits on how much data and state a peer can make us hold.
+///
+/// A peer that exceeds a handshake limit causes the connection to be closed with `H3_EXCESSIVE_LOAD`.
+/// A peer that exceeds a stream limit causes only that stream to be rejected.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Limits {
+    /// The maximum size of the CONNECT request or response headers, advertised via `SETTINGS_MAX_FIELD_SECTION_SIZE`.
+    /// This is enforced against the size of the encoded HEADERS frame.
+    pub max_field_section_size: u64,
+
+    /// The maximum size of the peer's SETTINGS frame.
+    pub max_settings_size: usize,
+
+    /// The maximum number of bytes buffered while decoding a capsule on the CONNECT stream.
+    pub max_capsule_size: usize,
+
+    /// The maximum number of incoming streams, per direction, that can be waiting for their stream header.
+    ///
+    /// Additional streams are rejected with `H3_EXCESSIVE_LOAD`.
+    pub max_pending_streams: usize,
+
+    /// How long to wait for an incoming stream's header before rejecting it with `H3_REQUEST_INCOMPLETE`.
+    pub stream_header_timeout: Duration,
+
+    /// The maximum number of WebTransport streams, per direction, that can arrive before the session is established.
+    ///
+    /// These are delivered once the session is accepted, in the order they were opened unless a header is still incomplete.
+    /// Additional streams, or all of them if the session is rejected, are reset with `WT_BUFFERED_STREAM_REJECTED`.
+    pub max_buffered_streams: usize,
+
+    /// The maximum number of QUIC datagrams that can arrive before the CONNECT request.
+    ///
+    /// These are delivered once the session is accepted, and additional datagrams are dropped.
+    /// Datagrams that arrive after the CONNECT request but before the session is accepted are buffered by QUIC instead, see [quinn::TransportConfig::datagram_receive_buffer_size].
+    pub max_buffered_datagrams: usize,
+
+    /// The number of bytes the peer can send across all streams in the session, advertised via `SETTINGS_WT_INITIAL_MAX_DATA`.
+    ///
+    /// More credit is granted with `WT_MAX_DATA` capsules as the application reads data.
+    /// This is always advertised, but `WT_MAX_DATA` is only sent if the peer also advertises session flow control.
+    ///
+    /// Quinn only reports stream data once it's read, so this limit isn't enforced exactly.
+    /// Instead the client and server builders set [quinn::TransportConfig::receive_window] to this value plus some slack for HTTP/3 framing,
+    /// which bounds the data received across the whole connection. Configure that yourself when using a custom endpoint.
+    pub initial_max_data: u64,
+
+    /// The number of unidirectional streams the peer can open, advertised via `SETTINGS_WT_INITIAL_MAX_STREAMS_UNI`.
+    ///
+    /// More credit is granted with `WT_MAX_STREAMS` capsules as streams are closed, so this is effectively a concurrency limit.
+    /// This is only enforced if the peer also advertises session flow control.
+    pub initial_max_streams_uni: u64,
+
+    /// The number of bidirectional streams the peer can open, advertised via `SETTINGS_WT_INITIAL_MAX_STREAMS_BIDI`.
+    ///
+    /// Like [Self::initial_max_streams_uni], this is effectively a concurrency limit.
+    pub initial_max_streams_bi: u64,
+
+    /// The maximum number of DATAGRAM capsules received on the CONNECT stream but not yet read by the application.
+    ///
+    /// Additional datagrams are dropped, just like QUIC datagrams that exceed [quinn::TransportConfig::datagram_receive_buffer_size].
+    pub max_datagram_capsules: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            // Matches what Chrome advertises.
+            max_field_section_size: 16 * 1024,
+            max_settings_size: 4 * 1024,
+            max_capsule_size: 16 * 1024,
+            max_pending_streams: 256,
+            stream_header_timeout: Duration::from_secs(10),
+            max_buffered_streams: 32,
+            max_buffered_datagrams: 32,
+            initial_max_data: 16 * 1024 * 1024,
+            initial_max_streams_uni: 100,
+            initial_max_streams_bi: 100,
+            max_datagram_capsules: 64,
+        }
+    }
+}
+
+impl Limits {
+    // The QUIC connection receive window, which approximates the session data limit as data arrives.
+    // We can't see stream data until the application reads it, so we rely on QUIC instead.
+    // Like WT_MAX_DATA, QUIC grants more credit as data is read, so a peer that respects our limit never hits it.
+    // The slack covers the HTTP/3 streams and stream headers, which also count against the QUIC limit.
+    pub(crate) fn receive_window(&self) -> quinn::VarInt {
+        let slack = self.max_settings_size as u64
+            + self.max_capsule_size as u64
+            + self.max_field_section_size;
+        let window = self.initial_max_data.saturating_add(slack);
+        quinn::VarInt::from_u64(window).unwrap_or(quinn::VarInt::MAX)
+    }
+}
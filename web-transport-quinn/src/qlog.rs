@@ -0,0 +1,313 @@
+// Function bodies in this module are cfg'd out without the qlog feature.
+#![cfg_attr(not(feature = "qlog"), allow(unused_variables))]
+
+#[cfg(feature = "qlog")]
+use std::{
+    fs, io,
+    io::Write,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Instant, SystemTime},
+};
+
+#[cfg(feature = "qlog")]
+use serde_json::json;
+
+use url::Url;
+
+/// The endpoint that performed an action, as recorded in qlog.
+#[derive(Clone, Copy)]
+pub(crate) enum Owner {
+    Local,
+    Remote,
+}
+
+#[cfg(feature = "qlog")]
+impl Owner {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Remote => "remote",
+        }
+    }
+}
+
+/// Records HTTP/3 and WebTransport events to a connection's qlog file.
+///
+/// This is a no-op unless the `qlog` feature is enabled and a qlog directory was configured.
+#[derive(Clone, Default)]
+pub(crate) struct Qlog {
+    #[cfg(feature = "qlog")]
+    file: Option<Arc<QlogFile>>,
+}
+
+impl Qlog {
+    pub fn settings(
+        &self,
+        owner: Owner,
+        stream_id: quinn::StreamId,
+        settings: &web_transport_proto::Settings,
+    ) {
+        #[cfg(feature = "qlog")]
+        {
+            let settings: Vec<_> = settings
+                .iter()
+                .map(
+                    |(id, value)| json!({ "name": format!("{id:?}"), "value": value.into_inner() }),
+                )
+                .collect();
+
+            let data = json!({
+                "owner": owner.as_str(),
+                "stream_id": u64::from(stream_id),
+                "settings": settings,
+            });
+
+            self.emit("http3:parameters_set", data);
+        }
+    }
+
+    pub fn connect_request(&self, owner: Owner, stream_id: quinn::StreamId, url: &Url) {
+        #[cfg(feature = "qlog")]
+        {
+            let path = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            };
+
+            let headers = json!([
+                { "name": ":method", "value": "CONNECT" },
+                { "name": ":protocol", "value": "webtransport" },
+                { "name": ":scheme", "value": url.scheme() },
+                { "name": ":authority", "value": url.authority() },
+                { "name": ":path", "value": path },
+            ]);
+
+            self.headers(owner, stream_id, headers);
+        }
+    }
+
+    pub fn connect_response(
+        &self,
+        owner: Owner,
+        stream_id: quinn::StreamId,
+        status: http::StatusCode,
+    ) {
+        #[cfg(feature = "qlog")]
+        {
+            let headers = json!([{ "name": ":status", "value": status.as_str() }]);
+            self.headers(owner, stream_id, headers);
+        }
+    }
+
+    #[cfg(feature = "qlog")]
+    fn headers(&self, owner: Owner, stream_id: quinn::StreamId, headers: serde_json::Value) {
+        let name = match owner {
+            Owner::Local => "http3:frame_created",
+            Owner::Remote => "http3:frame_parsed",
+        };
+
+        let data = json!({
+            "stream_id": u64::from(stream_id),
+            "frame": { "frame_type": "headers", "headers": headers },
+        });
+
+        self.emit(name, data);
+    }
+
+    pub fn stream_type(
+        &self,
+        owner: Owner,
+        stream_id: quinn::StreamId,
+        typ: web_transport_proto::VarInt,
+    ) {
+        #[cfg(feature = "qlog")]
+        {
+            use web_transport_proto::{Frame, StreamUni};
+
+            let stream_type = match stream_id.dir() {
+                quinn::Dir::Uni => match StreamUni(typ) {
+                    StreamUni::CONTROL => "control",
+                    StreamUni::PUSH => "push",
+                    StreamUni::QPACK_ENCODER => "qpack_encode",
+                    StreamUni::QPACK_DECODER => "qpack_decode",
+                    StreamUni::WEBTRANSPORT => "webtransport",
+                    _ => "unknown",
+                },
+                quinn::Dir::Bi => match Frame(typ) {
+                    Frame::WEBTRANSPORT => "webtransport",
+                    _ => "unknown",
+                },
+            };
+
+            let data = json!({
+                "owner": owner.as_str(),
+                "stream_id": u64::from(stream_id),
+                "stream_type": stream_type,
+                "stream_type_value": typ.into_inner(),
+            });
+
+            self.emit("http3:stream_type_set", data);
+        }
+    }
+
+    pub fn capsule(
+        &self,
+        owner: Owner,
+        stream_id: quinn::StreamId,
+        typ: web_transport_proto::VarInt,
+        length: usize,
+    ) {
+        #[cfg(feature = "qlog")]
+        {
+            let data = json!({
+                "stream_id": u64::from(stream_id),
+                "capsule_type": typ.into_inner(),
+                "length": length,
+            });
+
+            let name = match owner {
+                Owner::Local => "webtransport:capsule_created",
+                Owner::Remote => "webtransport:capsule_parsed",
+            };
+
+            self.emit(name, data);
+        }
+    }
+
+    pub fn session_closed(&self, owner: Owner, code: u32, reason: &str) {
+        #[cfg(feature = "qlog")]
+        {
+            let data = json!({ "owner": owner.as_str(), "code": code, "reason": reason });
+            self.emit("webtransport:session_closed", data);
+        }
+    }
+
+    #[cfg(feature = "qlog")]
+    fn emit(&self, name: &str, data: serde_json::Value) {
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let time = file.start.elapsed().as_secs_f64() * 1000.0;
+        let event = json!({ "time": time, "name": name, "data": data });
+
+        // JSON-SEQ records start with a record separator and end with a newline.
+        let mut record = vec![0x1e];
+        serde_json::to_writer(&mut record, &event).unwrap();
+        record.push(b'\n');
+
+        if let Err(err) = file.write_record(&record) {
+            log::warn!("failed to write qlog event: {err}");
+        }
+    }
+}
+
+/// Writes a qlog JSON-SEQ file for each new connection into a directory.
+#[cfg(feature = "qlog")]
+#[derive(Clone)]
+pub(crate) struct QlogDir {
+    path: PathBuf,
+    role: &'static str,
+
+    // QUIC events are configured per connection, so we need to build a new transport each time.
+    transport: Arc<dyn Fn() -> quinn::TransportConfig + Send + Sync>,
+}
+
+#[cfg(feature = "qlog")]
+impl QlogDir {
+    pub fn new<F>(path: PathBuf, role: &'static str, transport: F) -> Self
+    where
+        F: Fn() -> quinn::TransportConfig + Send + Sync + 'static,
+    {
+        Self {
+            path,
+            role,
+            transport: Arc::new(transport),
+        }
+    }
+
+    /// Create a new file, returning the handle for our events and a transport that writes QUIC events.
+    pub fn create(&self, remote: SocketAddr) -> io::Result<(Qlog, quinn::TransportConfig)> {
+        // Used to avoid collisions when multiple connections are created in the same millisecond.
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+
+        let path = self.path.join(format!("{now}-{id}-{}.sqlog", self.role));
+        let file = Arc::new(QlogFile {
+            file: Mutex::new(fs::File::create(&path)?),
+            start: Instant::now(),
+        });
+
+        log::debug!("writing qlog to {}", path.display());
+
+        // Quinn writes the header and QUIC events through a separate writer into the same file.
+        let mut config = quinn::QlogConfig::default();
+        config
+            .writer(Box::new(QlogWriter {
+                file: file.clone(),
+                pending: Vec::new(),
+            }))
+            .title(Some("web-transport-quinn".to_string()))
+            .description(Some(format!("{} {remote}", self.role)))
+            .start_time(file.start);
+
+        let stream = config
+            .into_stream()
+            .ok_or_else(|| io::Error::other("failed to start qlog stream"))?;
+
+        let mut transport = (self.transport)();
+        transport.qlog_stream(Some(stream));
+
+        Ok((Qlog { file: Some(file) }, transport))
+    }
+}
+
+#[cfg(feature = "qlog")]
+struct QlogFile {
+    file: Mutex<fs::File>,
+    start: Instant,
+}
+
+#[cfg(feature = "qlog")]
+impl QlogFile {
+    // Records are written with a single call so they don't interleave with quinn's.
+    fn write_record(&self, record: &[u8]) -> io::Result<()> {
+        self.file.lock().unwrap().write_all(record)
+    }
+}
+
+// Quinn serializes each record with multiple writes, so buffer until we have a full line.
+#[cfg(feature = "qlog")]
+struct QlogWriter {
+    file: Arc<QlogFile>,
+    pending: Vec<u8>,
+}
+
+#[cfg(feature = "qlog")]
+impl io::Write for QlogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        if let Some(end) = self.pending.iter().rposition(|b| *b == b'\n') {
+            let rest = self.pending.split_off(end + 1);
+            self.file.write_record(&self.pending)?;
+            self.pending = rest;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.file.lock().unwrap().flush()
+    }
+}
@@ -1,22 +1,37 @@
 use std::{
     io,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
 use bytes::Bytes;
 
-use crate::{ReadError, ReadExactError, ReadToEndError, SessionError};
+use crate::{OpenStream, ReadError, ReadExactError, ReadToEndError, SessionError, StreamCredit};
 
 /// A stream that can be used to recieve bytes. See [`quinn::RecvStream`].
 #[derive(Debug)]
 pub struct RecvStream {
     inner: quinn::RecvStream,
+
+    // Counts the stream as open in the session stats until dropped.
+    _open: Arc<OpenStream>,
+
+    // Data read from the stream grants the peer more session flow control credit.
+    credit: Arc<StreamCredit>,
 }
 
 impl RecvStream {
-    pub(crate) fn new(stream: quinn::RecvStream) -> Self {
-        Self { inner: stream }
+    pub(crate) fn new(
+        stream: quinn::RecvStream,
+        open: Arc<OpenStream>,
+        credit: Arc<StreamCredit>,
+    ) -> Self {
+        Self {
+            inner: stream,
+            _open: open,
+            credit,
+        }
     }
 
     /// Tell the other end to stop sending data with the given error code. See [`quinn::RecvStream::stop`].
@@ -31,12 +46,16 @@ impl RecvStream {
 
     /// Read some data into the buffer and return the amount read. See [`quinn::RecvStream::read`].
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<Option<usize>, ReadError> {
-        self.inner.read(buf).await.map_err(Into::into)
+        let size = self.inner.read(buf).await?;
+        self.credit.recv_data(size.unwrap_or(0));
+        Ok(size)
     }
 
     /// Fill the entire buffer with data. See [`quinn::RecvStream::read_exact`].
     pub async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), ReadExactError> {
-        self.inner.read_exact(buf).await.map_err(Into::into)
+        self.inner.read_exact(buf).await?;
+        self.credit.recv_data(buf.len());
+        Ok(())
     }
 
     /// Read a chunk of data from the stream. See [`quinn::RecvStream::read_chunk`].
@@ -45,20 +64,39 @@ impl RecvStream {
         max_length: usize,
         ordered: bool,
     ) -> Result<Option<quinn::Chunk>, ReadError> {
-        self.inner
-            .read_chunk(max_length, ordered)
-            .await
-            .map_err(Into::into)
+        let chunk = self.inner.read_chunk(max_length, ordered).await?;
+        if let Some(chunk) = &chunk {
+            self.credit.recv_data(chunk.bytes.len());
+        }
+
+        Ok(chunk)
     }
 
     /// Read chunks of data from the stream. See [`quinn::RecvStream::read_chunks`].
     pub async fn read_chunks(&mut self, bufs: &mut [Bytes]) -> Result<Option<usize>, ReadError> {
-        self.inner.read_chunks(bufs).await.map_err(Into::into)
+        let count = self.inner.read_chunks(bufs).await?;
+        if let Some(count) = count {
+            let size = bufs[..count].iter().map(|buf| buf.len()).sum();
+            self.credit.recv_data(size);
+        }
+
+        Ok(count)
     }
 
     /// Read until the end of the stream or the limit is hit. See [`quinn::RecvStream::read_to_end`].
     pub async fn read_to_end(&mut self, size_limit: usize) -> Result<Vec<u8>, ReadToEndError> {
-        self.inner.read_to_end(size_limit).await.map_err(Into::into)
+        // Read chunk by chunk so the peer gets more session credit before the stream is finished.
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = self.read_chunk(usize::MAX, true).await? {
+            if buf.len() + chunk.bytes.len() > size_limit {
+                return Err(ReadToEndError::TooLong);
+            }
+
+            buf.extend_from_slice(&chunk.bytes);
+        }
+
+        Ok(buf)
     }
 
     /// Block until the stream has been reset and return the error code. See [`quinn::RecvStream::received_reset`].
@@ -84,7 +122,10 @@ impl tokio::io::AsyncRead for RecvStream {
         cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf,
     ) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.inner).poll_read(cx, buf)
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.credit.recv_data(buf.filled().len() - filled);
+        res
     }
 }
 
@@ -105,6 +146,18 @@ impl web_transport_trait::RecvStream for RecvStream {
             .map(|r| r.map(|chunk| chunk.bytes))
     }
 
+    async fn read_chunk_unordered(
+        &mut self,
+        max: usize,
+    ) -> Result<Option<web_transport_trait::Chunk>, Self::Error> {
+        self.read_chunk(max, false).await.map(|r| {
+            r.map(|chunk| web_transport_trait::Chunk {
+                offset: chunk.offset,
+                bytes: chunk.bytes,
+            })
+        })
+    }
+
     async fn closed(&mut self) -> Result<(), Self::Error> {
         self.received_reset().await?;
         Ok(())
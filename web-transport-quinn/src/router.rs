@@ -0,0 +1,332 @@
+use std::{future::Future, sync::Arc};
+
+use futures::future::BoxFuture;
+use tokio::sync::Semaphore;
+
+use crate::{Incoming, Request, Server};
+
+/// Handles a WebTransport [Request] matched by a [Router].
+///
+/// This is implemented for any async function or closure that takes a [Request] and [Params].
+/// The handler decides whether to accept the session with [Request::ok] or reject it with [Request::close].
+pub trait RouteHandler: Send + Sync {
+    /// Called for each request with a URL path that matches the route.
+    fn handle(&self, request: Request, params: Params) -> BoxFuture<'static, ()>;
+}
+
+impl<F, Fut> RouteHandler for F
+where
+    F: Fn(Request, Params) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn handle(&self, request: Request, params: Params) -> BoxFuture<'static, ()> {
+        Box::pin(self(request, params))
+    }
+}
+
+/// The parameters extracted from the URL path by a [Router], such as `id` for `/rooms/:id`.
+///
+/// Values are taken from the path as-is, so they're still percent-encoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    params: Vec<(String, String)>,
+}
+
+impl Params {
+    /// Returns the value of the named parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns each parameter name and value, in the order they appear in the pattern.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The number of parameters.
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns true if there are no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+}
+
+/// Routes incoming WebTransport requests to handlers based on the URL path.
+///
+/// Patterns are split into segments by `/`:
+///   - `rooms` only matches the same segment.
+///   - `:name` matches any single segment, available as a parameter.
+///   - `*name` matches the rest of the path, including any `/`, and must be the last segment.
+///
+/// Routes are tried in the order they were added, and requests that don't match any route are rejected with a 404.
+/// Empty segments are ignored, so `/rooms/` matches the same routes as `/rooms`.
+///
+/// Use [Self::handle] as the body of an accept loop, or [Self::serve] to spawn a task for each request.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    concurrency: Option<usize>,
+}
+
+struct Route {
+    pattern: Vec<Segment>,
+    handler: Arc<dyn RouteHandler>,
+}
+
+enum Segment {
+    Static(String),
+    Param(String),
+    Rest(String),
+}
+
+impl Router {
+    /// Create a router without any routes, which rejects every request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a route for the given path pattern.
+    ///
+    /// # Panics
+    /// Panics if a `*name` segment isn't the last one in the pattern.
+    pub fn route(mut self, pattern: &str, handler: impl RouteHandler + 'static) -> Self {
+        let pattern: Vec<_> = segments(pattern)
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Rest(name.to_string())
+                } else {
+                    Segment::Static(segment.to_string())
+                }
+            })
+            .collect();
+
+        // NOTE: Option::is_none_or requires Rust 1.82.
+        #[allow(clippy::unnecessary_map_or)]
+        let valid = pattern
+            .iter()
+            .position(|s| matches!(s, Segment::Rest(_)))
+            .map_or(true, |index| index + 1 == pattern.len());
+        assert!(valid, "wildcard must be the last segment: {pattern:?}");
+
+        self.routes.push(Route {
+            pattern,
+            handler: Arc::new(handler),
+        });
+
+        self
+    }
+
+    /// Limit the number of requests handled at once by [Self::serve].
+    ///
+    /// New connections aren't accepted until a handler finishes. Defaults to no limit.
+    pub fn with_concurrency(self, max: usize) -> Self {
+        Self {
+            concurrency: Some(max),
+            ..self
+        }
+    }
+
+    /// Run the handler for the first route matching the URL path, otherwise reject the request with a 404.
+    pub async fn handle(&self, request: Request) {
+        let path = request.url().path().to_string();
+
+        for route in &self.routes {
+            if let Some(params) = route.matches(&path) {
+                log::debug!("routing request: path={path}");
+                return route.handler.handle(request, params).await;
+            }
+        }
+
+        log::debug!("no route for request: path={path}");
+        if let Err(err) = request.close(http::StatusCode::NOT_FOUND).await {
+            log::warn!("failed to reject request: path={path} err={err}");
+        }
+    }
+
+    /// Accept requests from the server until it's closed, handling each one in a new task.
+    ///
+    /// Raw QUIC connections from [crate::ServerBuilder::with_alpns] are not routed and are closed.
+    pub async fn serve(self, server: &mut Server) {
+        let semaphore = self.concurrency.map(|max| Arc::new(Semaphore::new(max)));
+        let router = Arc::new(self);
+
+        while let Some(incoming) = server.accept().await {
+            let request = match incoming {
+                Incoming::WebTransport(request) => request,
+                Incoming::Raw { alpn, .. } => {
+                    log::debug!("ignoring raw QUIC connection: alpn={alpn:?}");
+                    continue;
+                }
+            };
+
+            // Wait for a handler to finish if we're at the limit.
+            let permit = match &semaphore {
+                Some(semaphore) => Some(semaphore.clone().acquire_owned().await.unwrap()),
+                None => None,
+            };
+
+            let router = router.clone();
+            tokio::spawn(async move {
+                router.handle(request).await;
+                drop(permit);
+            });
+        }
+    }
+}
+
+impl Route {
+    // Returns the extracted parameters if the path matches the pattern.
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut params = Vec::new();
+        let mut path = segments(path);
+
+        for segment in &self.pattern {
+            match segment {
+                Segment::Static(expected) => {
+                    if path.next()? != expected {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.push((name.clone(), path.next()?.to_string()));
+                }
+                Segment::Rest(name) => {
+                    let rest: Vec<_> = path.by_ref().collect();
+                    params.push((name.clone(), rest.join("/")));
+                }
+            }
+        }
+
+        // Make sure there's nothing left over.
+        if path.next().is_some() {
+            return None;
+        }
+
+        Some(Params { params })
+    }
+}
+
+impl std::fmt::Debug for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Static(segment) => write!(f, "{segment}"),
+            Self::Param(name) => write!(f, ":{name}"),
+            Self::Rest(name) => write!(f, "*{name}"),
+        }
+    }
+}
+
+// Split a path into segments, ignoring any empty ones.
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(pattern: &str) -> Route {
+        let mut router = Router::new().route(pattern, |_: Request, _: Params| async {});
+        router.routes.pop().unwrap()
+    }
+
+    fn params(params: &[(&str, &str)]) -> Option<Params> {
+        let params = params
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Some(Params { params })
+    }
+
+    #[test]
+    fn test_static() {
+        let route = route("/rooms/lobby");
+        assert_eq!(route.matches("/rooms/lobby"), params(&[]));
+        assert_eq!(route.matches("/rooms"), None);
+        assert_eq!(route.matches("/rooms/other"), None);
+        assert_eq!(route.matches("/rooms/lobby/extra"), None);
+    }
+
+    #[test]
+    fn test_param() {
+        let route = route("/rooms/:id/users/:user");
+        assert_eq!(
+            route.matches("/rooms/42/users/alice"),
+            params(&[("id", "42"), ("user", "alice")])
+        );
+
+        // A parameter matches exactly one segment.
+        assert_eq!(route.matches("/rooms/42/users"), None);
+        assert_eq!(route.matches("/rooms/42/users/alice/extra"), None);
+
+        // Values are still percent-encoded.
+        assert_eq!(
+            route.matches("/rooms/a%2Fb/users/c"),
+            params(&[("id", "a%2Fb"), ("user", "c")])
+        );
+    }
+
+    #[test]
+    fn test_rest() {
+        let route = route("/files/*path");
+        assert_eq!(route.matches("/files/a/b/c"), params(&[("path", "a/b/c")]));
+        assert_eq!(route.matches("/files/a"), params(&[("path", "a")]));
+
+        // The rest of the path can be empty.
+        assert_eq!(route.matches("/files"), params(&[("path", "")]));
+        assert_eq!(route.matches("/other/a"), None);
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        // A trailing slash on either the pattern or the path is ignored.
+        assert_eq!(route("/rooms/").matches("/rooms"), params(&[]));
+        assert_eq!(route("/rooms").matches("/rooms/"), params(&[]));
+        assert_eq!(
+            route("/rooms/:id").matches("/rooms/42/"),
+            params(&[("id", "42")])
+        );
+
+        // The slash doesn't count as an empty segment.
+        assert_eq!(route("/rooms/:id").matches("/rooms/"), None);
+        assert_eq!(
+            route("/files/*path").matches("/files/a/b/"),
+            params(&[("path", "a/b")])
+        );
+    }
+
+    #[test]
+    fn test_empty_segments() {
+        // Repeated slashes are ignored.
+        assert_eq!(
+            route("/rooms/:id").matches("//rooms//42"),
+            params(&[("id", "42")])
+        );
+        assert_eq!(
+            route("/files/*path").matches("/files//a//b"),
+            params(&[("path", "a/b")])
+        );
+
+        // The root only matches an empty pattern.
+        assert_eq!(route("/").matches("/"), params(&[]));
+        assert_eq!(route("").matches("/"), params(&[]));
+        assert_eq!(route("/").matches("/rooms"), None);
+        assert_eq!(route("/:id").matches("/"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard must be the last segment")]
+    fn test_rest_not_last() {
+        route("/files/*path/edit");
+    }
+}
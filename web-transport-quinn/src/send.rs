@@ -1,12 +1,18 @@
 use std::{
+    future::{poll_fn, Future},
     io,
-    pin::Pin,
-    task::{Context, Poll},
+    pin::{pin, Pin},
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Instant,
 };
 
 use bytes::{Buf, Bytes};
+use tokio::sync::Notify;
 
-use crate::{ClosedStream, SessionError, WriteError};
+use crate::{
+    ClosedStream, OpenStream, SendGroup, SendGroupMember, SessionError, StreamCredit, WriteError,
+};
 
 /// A stream that can be used to send bytes. See [`quinn::SendStream`].
 ///
@@ -14,20 +20,208 @@ use crate::{ClosedStream, SessionError, WriteError};
 /// WebTransport uses u32 error codes and they're mapped in a reserved HTTP/3 error space.
 #[derive(Debug)]
 pub struct SendStream {
-    stream: quinn::SendStream,
+    // Shared with the deadline task, so it can reset the stream while it's idle or after it's dropped.
+    // The lock is never held across an await.
+    stream: Arc<Mutex<quinn::SendStream>>,
+
+    // Cached so we don't need the lock.
+    id: quinn::StreamId,
+
+    // Counts the stream as open in the session stats until dropped.
+    _open: Arc<OpenStream>,
+
+    // Data written to the stream counts against the session flow control limit.
+    credit: Arc<StreamCredit>,
+
+    // The send group this stream belongs to, which is the session's default group unless set.
+    group: SendGroupMember,
+    default_group: SendGroup,
+
+    // Reset the stream if it's not acknowledged by this time.
+    deadline: Option<Deadline>,
+
+    // Shared with the task that enforces the deadline, if one is running.
+    timer: Option<Arc<DeadlineTimer>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Deadline {
+    at: tokio::time::Instant,
+    code: u32,
+}
+
+// The deadline as seen by the background task, which exits when it's cleared.
+#[derive(Debug, Default)]
+struct DeadlineTimer {
+    deadline: Mutex<Option<Deadline>>,
+
+    // Notified when the deadline changes.
+    notify: Notify,
+}
+
+impl DeadlineTimer {
+    fn set(&self, deadline: Option<Deadline>) {
+        *self.deadline.lock().unwrap() = deadline;
+        self.notify.notify_one();
+    }
+
+    // Reset the stream if it's not acknowledged or stopped before the deadline.
+    async fn run(self: Arc<Self>, stream: Arc<Mutex<quinn::SendStream>>) {
+        let (id, stopped) = {
+            let stream = stream.lock().unwrap();
+            (stream.id(), stream.stopped())
+        };
+        let mut stopped = pin!(stopped);
+
+        loop {
+            let Some(deadline) = *self.deadline.lock().unwrap() else {
+                return;
+            };
+
+            tokio::select! {
+                _ = &mut stopped => return,
+                _ = self.notify.notified() => {}
+                _ = tokio::time::sleep_until(deadline.at) => {
+                    log::debug!("stream deadline expired: id={id}");
+                    let code = SendStream::reset_code(deadline.code);
+                    stream.lock().unwrap().reset(code).ok();
+                    return;
+                }
+            }
+        }
+    }
 }
 
 impl SendStream {
-    pub(crate) fn new(stream: quinn::SendStream) -> Self {
-        Self { stream }
+    pub(crate) fn new(
+        stream: quinn::SendStream,
+        open: Arc<OpenStream>,
+        credit: Arc<StreamCredit>,
+        default_group: &SendGroup,
+    ) -> Self {
+        let id = stream.id();
+        let stream = Arc::new(Mutex::new(stream));
+        let group = SendGroupMember::new(default_group.clone(), &stream, id, 0);
+
+        Self {
+            id,
+            stream,
+            _open: open,
+            credit,
+            group,
+            default_group: default_group.clone(),
+            deadline: None,
+            timer: None,
+        }
+    }
+
+    // Poll a Quinn future until it's ready, locking the stream only while polling.
+    // This is only safe for Quinn futures that don't keep any state between polls.
+    async fn poll_stream<T>(
+        &self,
+        mut f: impl FnMut(&mut quinn::SendStream, &mut Context<'_>) -> Poll<T>,
+    ) -> T {
+        poll_fn(|cx| f(&mut self.stream.lock().unwrap(), cx)).await
+    }
+
+    async fn write_inner(&self, buf: &[u8]) -> Result<usize, quinn::WriteError> {
+        self.poll_stream(|stream, cx| pin!(stream.write(buf)).poll(cx))
+            .await
+    }
+
+    async fn write_chunks_inner(
+        &self,
+        bufs: &mut [Bytes],
+    ) -> Result<quinn::Written, quinn::WriteError> {
+        self.poll_stream(|stream, cx| pin!(stream.write_chunks(bufs)).poll(cx))
+            .await
+    }
+
+    // Wait for session flow control credit, returning up to `size` bytes.
+    async fn credit(&mut self, size: usize) -> Result<usize, WriteError> {
+        let credit = poll_fn(|cx| self.credit.poll_send(cx, size));
+        match Self::until(self.deadline, credit).await {
+            Some(size) => Ok(size),
+            None => Err(self.expire()),
+        }
+    }
+
+    // Run the future to completion, or return None if the deadline expires first.
+    async fn until<F: Future>(deadline: Option<Deadline>, fut: F) -> Option<F::Output> {
+        let Some(deadline) = deadline else {
+            return Some(fut.await);
+        };
+
+        // Don't start any work if the deadline already expired.
+        if deadline.at <= tokio::time::Instant::now() {
+            return None;
+        }
+
+        tokio::time::timeout_at(deadline.at, fut).await.ok()
+    }
+
+    // Reset the stream because the deadline expired.
+    fn expire(&mut self) -> WriteError {
+        if let Some(deadline) = self.deadline {
+            log::debug!("stream deadline expired: id={}", self.id);
+            self.reset(deadline.code).ok();
+        }
+
+        self.clear_deadline();
+        WriteError::DeadlineExceeded
+    }
+
+    /// Reset the stream with the given error code if it's not fully acknowledged by the deadline.
+    ///
+    /// This is useful for data that becomes worthless after some time, so the bandwidth can go to fresher streams.
+    /// A background task enforces the deadline even while the stream is idle, and after [Self::finish] or drop until the peer acknowledges everything.
+    /// Any write in progress when the deadline expires returns [WriteError::DeadlineExceeded].
+    pub fn set_deadline(&mut self, deadline: Instant, code: u32) {
+        let deadline = Deadline {
+            at: deadline.into(),
+            code,
+        };
+        self.deadline = Some(deadline);
+
+        if let Some(timer) = &self.timer {
+            timer.set(Some(deadline));
+            return;
+        }
+
+        // Without a runtime, the deadline is only checked when writing.
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+
+        let timer = Arc::new(DeadlineTimer::default());
+        timer.set(Some(deadline));
+        runtime.spawn(timer.clone().run(self.stream.clone()));
+        self.timer = Some(timer);
+    }
+
+    /// Remove the deadline set by [Self::set_deadline].
+    pub fn clear_deadline(&mut self) {
+        self.deadline = None;
+
+        // The task exits once it notices.
+        if let Some(timer) = self.timer.take() {
+            timer.set(None);
+        }
     }
 
     /// Abruptly reset the stream with the provided error code. See [`quinn::SendStream::reset`].
     /// This is a u32 with WebTransport because we share the error space with HTTP/3.
     pub fn reset(&mut self, code: u32) -> Result<(), ClosedStream> {
+        self.stream
+            .lock()
+            .unwrap()
+            .reset(Self::reset_code(code))
+            .map_err(Into::into)
+    }
+
+    fn reset_code(code: u32) -> quinn::VarInt {
         let code = web_transport_proto::error_to_http3(code);
-        let code = quinn::VarInt::try_from(code).unwrap();
-        self.stream.reset(code).map_err(Into::into)
+        quinn::VarInt::try_from(code).unwrap()
     }
 
     /// Wait until the stream has been stopped and return the error code. See [`quinn::SendStream::stopped`].
@@ -35,7 +229,19 @@ impl SendStream {
     /// Unlike Quinn, this returns None if the code is not a valid WebTransport error code.
     /// Also unlike Quinn, this returns a SessionError, not a StoppedError, because 0-RTT is not supported.
     pub async fn stopped(&mut self) -> Result<Option<u32>, SessionError> {
-        match self.stream.stopped().await {
+        let stopped = self.stream.lock().unwrap().stopped();
+        let mut stopped = pin!(stopped);
+
+        // Reset the stream if the deadline expires, then wait for the reset to be acknowledged instead.
+        let res = match Self::until(self.deadline, stopped.as_mut()).await {
+            Some(res) => res,
+            None => {
+                self.expire();
+                stopped.await
+            }
+        };
+
+        match res {
             Ok(Some(code)) => Ok(web_transport_proto::error_from_http3(code.into_inner())),
             Ok(None) => Ok(None),
             Err(quinn::StoppedError::ConnectionLost(e)) => Err(e.into()),
@@ -45,42 +251,167 @@ impl SendStream {
 
     // Unfortunately, we have to wrap WriteError for a bunch of functions.
 
+    // Each write may block on the session flow control limit, in addition to the stream limit.
+    // Each write also gives up when the deadline expires, returning the credit it didn't use.
+
     /// Write some data to the stream, returning the size written. See [`quinn::SendStream::write`].
     pub async fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
-        self.stream.write(buf).await.map_err(Into::into)
+        let size = self.credit(buf.len()).await?;
+        let Some(res) = Self::until(self.deadline, self.write_inner(&buf[..size])).await else {
+            self.credit.unsend(size);
+            return Err(self.expire());
+        };
+
+        // Return any credit we didn't use.
+        self.credit
+            .unsend(size - res.as_ref().copied().unwrap_or(0));
+        res.map_err(Into::into)
     }
 
     /// Write all of the data to the stream. See [`quinn::SendStream::write_all`].
-    pub async fn write_all(&mut self, buf: &[u8]) -> Result<(), WriteError> {
-        self.stream.write_all(buf).await.map_err(Into::into)
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> Result<(), WriteError> {
+        while !buf.is_empty() {
+            let size = self.write(buf).await?;
+            buf = &buf[size..];
+        }
+
+        Ok(())
     }
 
     /// Write chunks of data to the stream. See [`quinn::SendStream::write_chunks`].
     pub async fn write_chunks(&mut self, bufs: &mut [Bytes]) -> Result<quinn::Written, WriteError> {
-        self.stream.write_chunks(bufs).await.map_err(Into::into)
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        let size = self.credit(total).await?;
+
+        if size == total {
+            let Some(res) = Self::until(self.deadline, self.write_chunks_inner(bufs)).await else {
+                self.credit.unsend(size);
+                return Err(self.expire());
+            };
+            self.credit
+                .unsend(size - res.as_ref().map(|w| w.bytes).unwrap_or(0));
+            return res.map_err(Into::into);
+        }
+
+        // We don't have enough credit for everything, so write part of the first non-empty chunk.
+        let index = bufs.iter().position(|buf| !buf.is_empty()).unwrap();
+        let mut part = [bufs[index].slice(..size.min(bufs[index].len()))];
+
+        let Some(res) = Self::until(self.deadline, self.write_chunks_inner(&mut part)).await else {
+            self.credit.unsend(size);
+            return Err(self.expire());
+        };
+        let written = res.as_ref().map(|w| w.bytes).unwrap_or(0);
+        self.credit.unsend(size - written);
+        res?;
+
+        bufs[index].advance(written);
+        let chunks = index + bufs[index].is_empty() as usize;
+
+        Ok(quinn::Written {
+            bytes: written,
+            chunks,
+        })
     }
 
     /// Write a chunk of data to the stream. See [`quinn::SendStream::write_chunk`].
-    pub async fn write_chunk(&mut self, buf: Bytes) -> Result<(), WriteError> {
-        self.stream.write_chunk(buf).await.map_err(Into::into)
+    pub async fn write_chunk(&mut self, mut buf: Bytes) -> Result<(), WriteError> {
+        while !buf.is_empty() {
+            let size = self.credit(buf.len()).await?;
+            let mut chunk = [buf.split_to(size)];
+
+            // Quinn advances the chunk as it's written, so only return credit for what's left over.
+            let res = Self::until(self.deadline, self.write_all_inner(&mut chunk)).await;
+            self.credit.unsend(chunk[0].len());
+
+            match res {
+                Some(res) => res?,
+                None => return Err(self.expire()),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Write the stream header followed by the payload, so they can share a write call and packet.
+    // The header doesn't count against session flow control.
+    pub(crate) async fn write_with_header(
+        &mut self,
+        header: Bytes,
+        mut data: Bytes,
+    ) -> Result<(), WriteError> {
+        let size = self.credit(data.len()).await?;
+        let mut chunks = [header, data.split_to(size)];
+
+        // Quinn advances the chunks as they're written, so anything left over wasn't sent.
+        let res = self.write_all_inner(&mut chunks).await;
+        self.credit.unsend(chunks[1].len());
+        res?;
+
+        // Write whatever didn't fit in the session flow control window.
+        self.write_chunk(data).await
+    }
+
+    // Like quinn::SendStream::write_all_chunks, which can't be polled with poll_stream because it keeps state.
+    async fn write_all_inner(&self, bufs: &mut [Bytes]) -> Result<(), quinn::WriteError> {
+        while bufs.iter().any(|buf| !buf.is_empty()) {
+            self.write_chunks_inner(bufs).await?;
+        }
+
+        Ok(())
     }
 
     /// Write all of the chunks of data to the stream. See [`quinn::SendStream::write_all_chunks`].
     pub async fn write_all_chunks(&mut self, bufs: &mut [Bytes]) -> Result<(), WriteError> {
-        self.stream.write_all_chunks(bufs).await.map_err(Into::into)
+        for buf in bufs {
+            self.write_chunk(std::mem::take(buf)).await?;
+        }
+
+        Ok(())
     }
 
     /// Mark the stream as finished, such that no more data can be written. See [`quinn::SendStream::finish`].
     pub fn finish(&mut self) -> Result<(), ClosedStream> {
-        self.stream.finish().map_err(Into::into)
+        self.stream.lock().unwrap().finish().map_err(Into::into)
     }
 
+    /// Set the stream's priority. See [`quinn::SendStream::set_priority`].
+    ///
+    /// This orders the stream relative to other streams in the same [SendGroup], see its documentation for details.
     pub fn set_priority(&self, order: i32) -> Result<(), ClosedStream> {
-        self.stream.set_priority(order).map_err(Into::into)
+        // Make sure the stream is still open, like Quinn would.
+        self.stream.lock().unwrap().priority()?;
+        self.group.set_order(order, &self.stream);
+        Ok(())
     }
 
+    /// Get the stream's priority, as set by [Self::set_priority].
     pub fn priority(&self) -> Result<i32, ClosedStream> {
-        self.stream.priority().map_err(Into::into)
+        self.stream.lock().unwrap().priority()?;
+        Ok(self.group.order().unwrap_or_default())
+    }
+
+    /// Move the stream into a [SendGroup], or out of one with `None`.
+    ///
+    /// The stream keeps its priority, which now orders it relative to other streams in the group.
+    pub fn set_send_group(&mut self, group: Option<&SendGroup>) -> Result<(), ClosedStream> {
+        let order = self.priority()?;
+
+        let group = group.unwrap_or(&self.default_group);
+        if *group == self.group.group {
+            return Ok(());
+        }
+
+        // Joining the new group also leaves the old one, as the old membership is dropped.
+        self.group = SendGroupMember::new(group.clone(), &self.stream, self.id, order);
+
+        Ok(())
+    }
+
+    /// The [SendGroup] this stream belongs to, if any.
+    pub fn send_group(&self) -> Option<&SendGroup> {
+        let group = &self.group.group;
+        (*group != self.default_group).then_some(group)
     }
 }
 
@@ -90,16 +421,47 @@ impl tokio::io::AsyncWrite for SendStream {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
+        // There's no timer here, so the deadline is only checked when writing.
+        if let Some(deadline) = self.deadline {
+            if deadline.at <= tokio::time::Instant::now() {
+                let err = self.expire();
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, err)));
+            }
+        }
+
+        let size = ready!(self.credit.poll_send(cx, buf.len()));
+
         // We have to use this syntax because quinn added its own poll_write method.
-        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.stream), cx, buf)
+        let res = {
+            let mut stream = self.stream.lock().unwrap();
+            tokio::io::AsyncWrite::poll_write(Pin::new(&mut *stream), cx, &buf[..size])
+        };
+
+        // Return any credit we didn't use, including all of it if the write is pending.
+        let written = match &res {
+            Poll::Ready(Ok(written)) => *written,
+            _ => 0,
+        };
+        self.credit.unsend(size - written);
+
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.stream.lock().unwrap()).poll_flush(cx)
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.stream).poll_flush(cx)
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.stream.lock().unwrap()).poll_shutdown(cx)
     }
+}
 
-    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.stream).poll_shutdown(cx)
+impl Drop for SendStream {
+    fn drop(&mut self) {
+        // Quinn would finish the stream on drop, but the deadline task may keep it alive until it's acknowledged.
+        if let Ok(mut stream) = self.stream.lock() {
+            stream.finish().ok();
+        }
     }
 }
 
@@ -114,6 +476,10 @@ impl web_transport_trait::SendStream for SendStream {
         Self::reset(self, code).ok();
     }
 
+    fn set_deadline(&mut self, deadline: Instant, code: u32) {
+        Self::set_deadline(self, deadline, code);
+    }
+
     // Unlike Quinn, this will also block until the stream is closed.
     async fn finish(&mut self) -> Result<(), Self::Error> {
         Self::finish(self).map_err(|_| WriteError::ClosedStream)?;
@@ -142,3 +508,120 @@ impl web_transport_trait::SendStream for SendStream {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{testing, Limits};
+
+    fn deadline(ms: u64) -> Instant {
+        Instant::now() + Duration::from_millis(ms)
+    }
+
+    #[tokio::test]
+    async fn test_deadline_idle() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        // The stream is reset even though nothing is written after the deadline is set.
+        let mut send = client.open_uni().await.unwrap();
+        send.write_all(b"hello").await.unwrap();
+        send.set_deadline(deadline(100), 7);
+
+        let mut recv = server.accept_uni().await.unwrap();
+        assert!(recv.received_reset().await.unwrap().is_some());
+
+        // Writing after the deadline fails without sending anything.
+        let err = send.write_all(b"late").await.unwrap_err();
+        assert!(matches!(err, WriteError::DeadlineExceeded));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_dropped() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        let mut send = client.open_uni().await.unwrap();
+        send.write_all(b"hello").await.unwrap();
+        send.set_deadline(deadline(100), 7);
+        drop(send);
+
+        // The stream is finished on drop, so the peer can read everything before the deadline.
+        let mut recv = server.accept_uni().await.unwrap();
+        assert_eq!(recv.read_to_end(1024).await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_deadline_acknowledged() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        let mut send = client.open_uni().await.unwrap();
+        send.set_deadline(deadline(200), 7);
+        send.write_all(b"hello").await.unwrap();
+        send.finish().unwrap();
+        assert_eq!(send.stopped().await.unwrap(), None);
+
+        // Nothing happens when the deadline expires after everything was acknowledged.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let mut recv = server.accept_uni().await.unwrap();
+        assert_eq!(recv.read_to_end(1024).await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_deadline_cleared() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        let mut send = client.open_uni().await.unwrap();
+        send.set_deadline(deadline(100), 7);
+        send.clear_deadline();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        send.write_all(b"hello").await.unwrap();
+        send.finish().unwrap();
+
+        let mut recv = server.accept_uni().await.unwrap();
+        assert_eq!(recv.read_to_end(1024).await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_deadline_blocked_write() {
+        // The server only grants a little session credit, so the write blocks.
+        let limits = Limits {
+            initial_max_data: 16,
+            ..Default::default()
+        };
+        let (client, server) = testing::session(limits).await;
+
+        let mut send = client.open_uni().await.unwrap();
+        send.set_deadline(deadline(100), 7);
+
+        let err = send.write_all(&[0u8; 1024]).await.unwrap_err();
+        assert!(matches!(err, WriteError::DeadlineExceeded));
+
+        let mut recv = server.accept_uni().await.unwrap();
+        assert!(recv.received_reset().await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_deadline_partial_write() {
+        // The peer's stream window only fits part of the chunk, so the write blocks halfway through.
+        let transport = |config: &mut quinn::TransportConfig| {
+            config.stream_receive_window(1024u32.into());
+        };
+        let (client, _server) = testing::session_with(Limits::default(), transport, None).await;
+
+        let mut send = client.open_uni().await.unwrap();
+        send.set_deadline(deadline(100), 7);
+
+        let err = send
+            .write_chunk(Bytes::from(vec![0u8; 4096]))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WriteError::DeadlineExceeded));
+
+        // The written part still counts against the session limit, so only the rest is returned.
+        // The 3 byte stream header also used the stream window, but it doesn't count as session data.
+        assert_eq!(send.credit.data_sent(), 1024 - 3);
+    }
+}
@@ -1,6 +1,9 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use crate::{crypto, CongestionControl, Connect, ServerError, Session, Settings};
+use crate::{
+    crypto, transport_config, Admission, AdmissionPolicy, CongestionControl, Connect, HttpHandler,
+    Limits, Qlog, ServerError, Session, SessionAccept, Settings, ALPN,
+};
 
 use futures::{future::BoxFuture, stream::FuturesUnordered, StreamExt};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
@@ -14,6 +17,15 @@ pub struct ServerBuilder {
     addr: std::net::SocketAddr,
     congestion_controller:
         Option<Arc<dyn quinn::congestion::ControllerFactory + Send + Sync + 'static>>,
+    key_log: Option<Arc<dyn rustls::KeyLog>>,
+    handshake_timeout: Option<Duration>,
+    max_pending_handshakes: Option<usize>,
+    admission: Option<Arc<dyn AdmissionPolicy>>,
+    limits: Limits,
+    alpns: Vec<Vec<u8>>,
+    http: Option<Arc<dyn HttpHandler>>,
+    #[cfg(feature = "qlog")]
+    qlog: Option<std::path::PathBuf>,
 }
 
 impl Default for ServerBuilder {
@@ -29,6 +41,15 @@ impl ServerBuilder {
             provider: crypto::default_provider(),
             addr: "[::]:443".parse().unwrap(),
             congestion_controller: None,
+            key_log: None,
+            handshake_timeout: Some(Duration::from_secs(10)),
+            max_pending_handshakes: Some(1024),
+            admission: None,
+            limits: Limits::default(),
+            alpns: vec![ALPN.as_bytes().to_vec()],
+            http: None,
+            #[cfg(feature = "qlog")]
+            qlog: None,
         }
     }
 
@@ -53,6 +74,96 @@ impl ServerBuilder {
         self
     }
 
+    /// Fail any handshake that takes longer than the timeout, covering QUIC, SETTINGS, and CONNECT.
+    ///
+    /// Defaults to 10 seconds; None disables the timeout.
+    pub fn with_handshake_timeout(self, timeout: Option<Duration>) -> Self {
+        Self {
+            handshake_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Refuse new connections while this many handshakes are in progress.
+    ///
+    /// This includes plain HTTP/3 clients being served by [Self::with_http_handler].
+    /// Defaults to 1024; None disables the limit.
+    pub fn with_max_pending_handshakes(self, max: Option<usize>) -> Self {
+        Self {
+            max_pending_handshakes: max,
+            ..self
+        }
+    }
+
+    /// Decide whether to accept, refuse, ignore, or retry each connection before the handshake starts.
+    ///
+    /// See [RateLimit] and [RetryWhenBusy] for the built-in policies.
+    ///
+    /// [RateLimit]: crate::RateLimit
+    /// [RetryWhenBusy]: crate::RetryWhenBusy
+    pub fn with_admission(self, policy: impl AdmissionPolicy + 'static) -> Self {
+        Self {
+            admission: Some(Arc::new(policy)),
+            ..self
+        }
+    }
+
+    /// Limit how much data the peer can make us buffer. See [Limits].
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// Negotiate one of these ALPNs with the client, in order of preference.
+    ///
+    /// Connections using [ALPN] perform the WebTransport handshake, while any other ALPN is returned as a raw QUIC [Session].
+    /// Defaults to only [ALPN]; include it to keep accepting WebTransport sessions.
+    pub fn with_alpns(self, alpns: impl IntoIterator<Item = impl Into<Vec<u8>>>) -> Self {
+        Self {
+            alpns: alpns.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// Serve plain HTTP/3 requests, such as a GET, instead of closing the connection.
+    ///
+    /// Requests are handled until the client sends a WebTransport CONNECT request, or the handshake times out.
+    /// Clients that don't enable WebTransport in their SETTINGS are served until they disconnect instead,
+    /// and aren't returned by [Server::accept] at all. They count as a pending handshake until then.
+    pub fn with_http_handler(self, handler: impl HttpHandler + 'static) -> Self {
+        Self {
+            http: Some(Arc::new(handler)),
+            ..self
+        }
+    }
+
+    /// Log TLS secrets using the provided [rustls::KeyLog], allowing packet captures to be decrypted.
+    ///
+    /// This should only be used for debugging; anybody with access to the secrets can decrypt the traffic.
+    pub fn with_key_log(self, key_log: Arc<dyn rustls::KeyLog>) -> Self {
+        Self {
+            key_log: Some(key_log),
+            ..self
+        }
+    }
+
+    /// Log TLS secrets to the file specified by the `SSLKEYLOGFILE` environment variable, if set.
+    ///
+    /// The format is understood by Wireshark. See [Self::with_key_log].
+    pub fn with_key_log_file(self) -> Self {
+        self.with_key_log(Arc::new(rustls::KeyLogFile::new()))
+    }
+
+    /// Write a qlog file for each connection into the specified directory.
+    ///
+    /// The files include QUIC events as well as HTTP/3 and WebTransport events, and can be viewed with qvis.
+    #[cfg(feature = "qlog")]
+    pub fn with_qlog(self, dir: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            qlog: Some(dir.into()),
+            ..self
+        }
+    }
+
     /// Supply a certificate used for TLS.
     // TODO support multiple certs based on...?
     pub fn with_certificate(
@@ -66,78 +177,361 @@ impl ServerBuilder {
             .with_no_client_auth()
             .with_single_cert(chain, key)?;
 
-        config.alpn_protocols = vec![crate::ALPN.as_bytes().to_vec()]; // this one is important
+        config.alpn_protocols = self.alpns.clone(); // this one is important
+
+        if let Some(key_log) = self.key_log.clone() {
+            config.key_log = key_log;
+        }
 
         let config: quinn::crypto::rustls::QuicServerConfig = config.try_into().unwrap();
-        let config = quinn::ServerConfig::with_crypto(Arc::new(config));
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(config));
+
+        let transport = transport_config(self.congestion_controller.clone(), &self.limits);
+        config.transport_config(Arc::new(transport));
 
-        let server = quinn::Endpoint::server(config, self.addr)
+        let server = quinn::Endpoint::server(config.clone(), self.addr)
             .map_err(|e| ServerError::IoError(e.into()))?;
 
-        Ok(Server::new(server))
+        let mut server = Server::new(server);
+        server.handshake_timeout = self.handshake_timeout;
+        server.max_pending_handshakes = self.max_pending_handshakes;
+        server.admission = self.admission;
+        server.limits = self.limits;
+        server.http = self.http;
+
+        #[cfg(feature = "qlog")]
+        if let Some(path) = self.qlog {
+            let cc = self.congestion_controller;
+            let limits = server.limits.clone();
+            let dir = crate::QlogDir::new(path, "server", move || {
+                transport_config(cc.clone(), &limits)
+            });
+            server.qlog = Some((dir, config));
+        }
+
+        Ok(server)
+    }
+}
+
+/// A new connection returned by [Server::accept], depending on the negotiated ALPN.
+pub enum Incoming {
+    /// A WebTransport session request, negotiated with [ALPN].
+    WebTransport(Request),
+
+    /// A raw QUIC connection negotiated with another ALPN, see [ServerBuilder::with_alpns].
+    ///
+    /// The connection is wrapped with [Session::raw], using the server name sent by the client for the URL.
+    Raw { session: Session, alpn: Vec<u8> },
+}
+
+impl Incoming {
+    /// Returns the negotiated ALPN.
+    pub fn alpn(&self) -> &[u8] {
+        match self {
+            Self::WebTransport(_) => ALPN.as_bytes(),
+            Self::Raw { alpn, .. } => alpn,
+        }
     }
 }
 
+// A finished handshake, which doesn't always produce a connection for the application.
+// It's only returned from the handshake future, so the size difference doesn't matter.
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Handshake {
+    Incoming(Incoming),
+
+    // A plain HTTP/3 client, served by the HTTP handler until this resolves.
+    Http(BoxFuture<'static, ()>),
+}
+
+// The result of a handshake along with the remote address, see Server::accept_result.
+type Accepted = (SocketAddr, Result<Incoming, ServerError>);
+
 /// A WebTransport server that accepts new sessions.
 pub struct Server {
     endpoint: quinn::Endpoint,
-    accept: FuturesUnordered<BoxFuture<'static, Result<Request, ServerError>>>,
+    // Pending handshakes, including plain HTTP/3 clients that resolve to None once they disconnect.
+    accept: FuturesUnordered<BoxFuture<'static, Option<Accepted>>>,
+    handshake_timeout: Option<Duration>,
+    max_pending_handshakes: Option<usize>,
+    admission: Option<Arc<dyn AdmissionPolicy>>,
+    limits: Limits,
+    http: Option<Arc<dyn HttpHandler>>,
+    #[cfg(feature = "qlog")]
+    qlog: Option<(crate::QlogDir, quinn::ServerConfig)>,
 }
 
 impl Server {
     /// Manaully create a new server with a manually constructed Endpoint.
     ///
     /// NOTE: The ALPN must be set to `crate::ALPN` for WebTransport to work.
+    /// Any other negotiated ALPN is returned as [Incoming::Raw].
+    /// Unlike [ServerBuilder], there's no handshake timeout or limit on pending handshakes.
     pub fn new(endpoint: quinn::Endpoint) -> Self {
         Self {
             endpoint,
             accept: Default::default(),
+            handshake_timeout: None,
+            max_pending_handshakes: None,
+            admission: None,
+            limits: Limits::default(),
+            http: None,
+            #[cfg(feature = "qlog")]
+            qlog: None,
         }
     }
 
-    /// Accept a new WebTransport session Request from a client.
-    pub async fn accept(&mut self) -> Option<Request> {
+    /// Accept a new connection from a client, either a WebTransport session [Request] or a raw QUIC [Session].
+    ///
+    /// Failed handshakes are logged and skipped; use [Self::accept_result] to observe them.
+    pub async fn accept(&mut self) -> Option<Incoming> {
+        loop {
+            match self.accept_result().await? {
+                (_, Ok(incoming)) => return Some(incoming),
+                // Expected under load, so don't spam the logs.
+                (
+                    remote,
+                    Err(err @ (ServerError::TooManyHandshakes | ServerError::NotAdmitted)),
+                ) => {
+                    log::debug!("rejected session: remote={remote} err={err}")
+                }
+                (remote, Err(err)) => {
+                    log::warn!("failed to accept session: remote={remote} err={err}")
+                }
+            }
+        }
+    }
+
+    /// Returns the result of the next handshake along with the remote address, including any failures.
+    ///
+    /// This covers TLS, HTTP/3 SETTINGS, and CONNECT errors that [Self::accept] would otherwise skip.
+    /// Returns None when the endpoint is closed.
+    pub async fn accept_result(&mut self) -> Option<(SocketAddr, Result<Incoming, ServerError>)> {
         loop {
             tokio::select! {
                 res = self.endpoint.accept() => {
-                    let conn = res?;
-                    self.accept.push(Box::pin(async move {
-                        let conn = conn.await?;
-                        Request::accept(conn).await
-                    }));
+                    let incoming = res?;
+                    let remote = incoming.remote_address();
+
+                    // Refuse before doing any crypto if we're overloaded.
+                    if self.max_pending_handshakes.is_some_and(|max| self.accept.len() >= max) {
+                        incoming.refuse();
+                        return Some((remote, Err(ServerError::TooManyHandshakes)));
+                    }
+
+                    let incoming = match self.admit(incoming) {
+                        Ok(incoming) => incoming,
+                        // The client will try again with a validated address.
+                        Err(Admission::Retry) => continue,
+                        Err(_) => return Some((remote, Err(ServerError::NotAdmitted))),
+                    };
+
+                    #[cfg(feature = "tracing")]
+                    let span = tracing::info_span!(
+                        "handshake",
+                        remote = %remote,
+                        // Filled in by Request::accept_with once the CONNECT arrives.
+                        session_id = tracing::field::Empty,
+                        url = tracing::field::Empty,
+                    );
+
+                    let (conn, qlog) = self.connecting(incoming);
+                    let limits = self.limits.clone();
+                    let http = self.http.clone();
+                    let port = self.endpoint.local_addr().map(|addr| addr.port()).unwrap_or(443);
+                    let handshake = Self::handshake(conn, qlog, limits, http, port);
+
+                    #[cfg(feature = "tracing")]
+                    let handshake = tracing::Instrument::instrument(handshake, span);
+
+                    // Dropping the handshake on timeout also closes the connection.
+                    let timeout = self.handshake_timeout;
+                    let handshake = async move {
+                        let res = match timeout {
+                            Some(timeout) => tokio::time::timeout(timeout, handshake)
+                                .await
+                                .unwrap_or(Err(ServerError::HandshakeTimeout)),
+                            None => handshake.await,
+                        };
+
+                        let serve = match res {
+                            Ok(Handshake::Incoming(incoming)) => return Some((remote, Ok(incoming))),
+                            Ok(Handshake::Http(serve)) => serve,
+                            Err(err) => return Some((remote, Err(err))),
+                        };
+
+                        // Plain HTTP/3 clients aren't returned, but they still count as pending until they disconnect.
+                        serve.await;
+                        None
+                    };
+
+                    self.accept.push(Box::pin(handshake));
                 }
                 Some(res) = self.accept.next() => {
-                    if let Ok(session) = res {
-                        return Some(session)
+                    if let Some(res) = res {
+                        return Some(res);
                     }
                 }
             }
         }
     }
+
+    // Completes the QUIC handshake, then the HTTP/3 handshake if the client negotiated it.
+    async fn handshake(
+        conn: Result<quinn::Connecting, quinn::ConnectionError>,
+        qlog: Qlog,
+        limits: Limits,
+        http: Option<Arc<dyn HttpHandler>>,
+        port: u16,
+    ) -> Result<Handshake, ServerError> {
+        let conn = conn?.await?;
+
+        let handshake = conn
+            .handshake_data()
+            .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok());
+
+        let (alpn, server_name) = match handshake {
+            Some(data) => (data.protocol, data.server_name),
+            None => (None, None),
+        };
+
+        match alpn {
+            Some(alpn) if alpn != ALPN.as_bytes() => {
+                let url = raw_url(&conn, server_name.as_deref(), port);
+                let session = Session::raw(conn, url);
+                Ok(Handshake::Incoming(Incoming::Raw { session, alpn }))
+            }
+            _ => Request::accept_with(conn, qlog, limits, http).await,
+        }
+    }
+
+    // Applies the admission policy, returning the connection if the handshake should start.
+    // Otherwise, returns the decision that was carried out.
+    fn admit(&self, incoming: quinn::Incoming) -> Result<quinn::Incoming, Admission> {
+        let Some(policy) = &self.admission else {
+            return Ok(incoming);
+        };
+
+        match policy.admit(&incoming, self.accept.len()) {
+            Admission::Accept => Ok(incoming),
+            Admission::Retry if incoming.remote_address_validated() => Ok(incoming),
+            Admission::Retry => match incoming.retry() {
+                Ok(()) => Err(Admission::Retry),
+                // We can't send another Retry, so refuse instead.
+                Err(err) => {
+                    err.into_incoming().refuse();
+                    Err(Admission::Refuse)
+                }
+            },
+            Admission::Refuse => {
+                incoming.refuse();
+                Err(Admission::Refuse)
+            }
+            Admission::Ignore => {
+                incoming.ignore();
+                Err(Admission::Ignore)
+            }
+        }
+    }
+
+    // Accepts the connection, creating a qlog file if enabled.
+    fn connecting(
+        &self,
+        incoming: quinn::Incoming,
+    ) -> (Result<quinn::Connecting, quinn::ConnectionError>, Qlog) {
+        #[cfg(feature = "qlog")]
+        if let Some((dir, config)) = &self.qlog {
+            match dir.create(incoming.remote_address()) {
+                Ok((qlog, transport)) => {
+                    let mut config = config.clone();
+                    config.transport_config(Arc::new(transport));
+                    return (incoming.accept_with(Arc::new(config)), qlog);
+                }
+                Err(err) => log::warn!("failed to create qlog file: {err}"),
+            }
+        }
+
+        (incoming.accept(), Qlog::default())
+    }
+}
+
+// The URL for a raw QUIC connection, using the server name if the client sent one.
+fn raw_url(conn: &quinn::Connection, server_name: Option<&str>, port: u16) -> Url {
+    if let Some(url) =
+        server_name.and_then(|name| Url::parse(&format!("https://{name}:{port}/")).ok())
+    {
+        return url;
+    }
+
+    let ip = conn
+        .local_ip()
+        .unwrap_or(std::net::Ipv4Addr::UNSPECIFIED.into());
+    Url::parse(&format!("https://{}/", SocketAddr::new(ip, port))).unwrap()
 }
 
 /// A mostly complete WebTransport handshake, just awaiting the server's decision on whether to accept or reject the session based on the URL.
 pub struct Request {
     conn: quinn::Connection,
     settings: Settings,
-    connect: Connect,
+
+    // Boxed because it holds everything that arrived before the CONNECT request.
+    connect: Box<Connect>,
 }
 
 impl Request {
     /// Accept a new WebTransport session from a client.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "request",
+            skip_all,
+            fields(remote = %conn.remote_address(), session_id, url),
+            err
+        )
+    )]
     pub async fn accept(conn: quinn::Connection) -> Result<Self, ServerError> {
+        match Self::accept_with(conn, Qlog::default(), Limits::default(), None).await? {
+            Handshake::Incoming(Incoming::WebTransport(request)) => Ok(request),
+            // Without an HTTP handler, clients that don't support WebTransport fail the handshake.
+            _ => unreachable!("no HTTP handler"),
+        }
+    }
+
+    pub(crate) async fn accept_with(
+        conn: quinn::Connection,
+        qlog: Qlog,
+        limits: Limits,
+        http: Option<Arc<dyn HttpHandler>>,
+    ) -> Result<Handshake, ServerError> {
         // Perform the H3 handshake by sending/reciving SETTINGS frames.
-        let settings = Settings::connect(&conn).await?;
+        let settings = Settings::connect(&conn, &qlog, &limits, http.is_some()).await?;
+
+        // A plain HTTP/3 client will never send a CONNECT request, so serve it until it disconnects.
+        if let (0, Some(http)) = (settings.remote().supports_webtransport(), http.as_ref()) {
+            let http = http.clone();
+            return Ok(Handshake::Http(Box::pin(async move {
+                // Keep the control streams open until we're done.
+                let _settings = settings;
+                if let Err(err) = Connect::serve(&conn, &qlog, &limits, &http).await {
+                    log::debug!("HTTP/3 connection closed: {err}");
+                }
+            })));
+        }
 
         // Accept the CONNECT request but don't send a response yet.
-        let connect = Connect::accept(&conn).await?;
+        let connect = Connect::accept(&conn, qlog, limits, http.as_ref()).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current()
+            .record("session_id", tracing::field::display(connect.session_id()))
+            .record("url", tracing::field::display(connect.url()));
 
         // Return the resulting request with a reference to the settings/connect streams.
-        Ok(Self {
+        Ok(Handshake::Incoming(Incoming::WebTransport(Self {
             conn,
             settings,
-            connect,
-        })
+            connect: Box::new(connect),
+        })))
     }
 
     /// Returns the URL provided by the client.
@@ -148,12 +542,25 @@ impl Request {
     /// Accept the session, returning a 200 OK.
     pub async fn ok(mut self) -> Result<Session, quinn::WriteError> {
         self.connect.respond(http::StatusCode::OK).await?;
-        Ok(Session::new(self.conn, self.settings, self.connect))
+        Ok(Session::new(self.conn, self.settings, *self.connect))
     }
 
     /// Reject the session, returing your favorite HTTP status code.
+    ///
+    /// Any WebTransport streams that arrived before the session was established are reset with `WT_BUFFERED_STREAM_REJECTED`.
+    /// This waits until the client has received the response, so it isn't lost when the connection is dropped.
     pub async fn close(mut self, status: http::StatusCode) -> Result<(), quinn::WriteError> {
+        for stream in self.settings.take_streams() {
+            SessionAccept::reject_buffered_uni(stream);
+        }
+
+        for stream in self.connect.take_streams() {
+            SessionAccept::reject_buffered_bi(stream);
+        }
+
         self.connect.respond(status).await?;
+        self.connect.finish().await;
+
         Ok(())
     }
 }
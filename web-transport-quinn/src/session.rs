@@ -1,20 +1,27 @@
 use std::{
     fmt,
-    future::{poll_fn, Future},
+    future::poll_fn,
     io::Cursor,
     ops::Deref,
     pin::Pin,
     sync::{Arc, Mutex},
-    task::{ready, Context, Poll},
+    task::{ready, Context, Poll, Wake, Waker},
 };
 
 use bytes::{Bytes, BytesMut};
-use futures::stream::{FuturesUnordered, Stream, StreamExt};
-use tokio::io::AsyncReadExt;
+use futures::{Stream, StreamExt};
+use tokio::{
+    io::AsyncReadExt,
+    sync::{mpsc, watch},
+    time::Instant,
+};
 use url::Url;
 
 use crate::{
-    ClientError, Connect, RecvStream, SendStream, SessionError, Settings, WebTransportError,
+    ClientError, Connect, Control, DatagramDropPolicy, Datagrams, DatagramsTask, FlowControl,
+    Limits, Owner, PendingBi, PendingSet, PendingUni, Push, Qlog, QpackStreams, RecvStream,
+    SendDatagramError, SendGroup, SendStream, SessionCounters, SessionError, SessionStats,
+    Settings, StreamCredit, WebTransportError, WriteError,
 };
 
 use web_transport_proto::{Frame, StreamUni, VarInt};
@@ -35,12 +42,12 @@ pub struct Session {
     // The session ID, as determined by the stream ID of the connect request.
     session_id: Option<VarInt>,
 
-    // The accept logic is stateful, so use an Arc<Mutex> to share it.
-    accept: Option<Arc<Mutex<SessionAccept>>>,
+    // The accept logic is stateful, so use an Arc to share it.
+    accept: Option<Arc<SessionAccept>>,
 
     // Cache the headers in front of each stream we open.
-    header_uni: Vec<u8>,
-    header_bi: Vec<u8>,
+    header_uni: Bytes,
+    header_bi: Bytes,
     header_datagram: Vec<u8>,
 
     // Keep a reference to the settings and connect stream to avoid closing them until dropped.
@@ -49,10 +56,35 @@ pub struct Session {
 
     // The URL used to create the session.
     url: Url,
+
+    // Statistics that QUIC doesn't track for us.
+    counters: Arc<SessionCounters>,
+
+    // Streams that aren't in a SendGroup, which keep their own priority.
+    default_group: SendGroup,
+
+    // Records HTTP/3 and WebTransport events if qlog is enabled.
+    qlog: Qlog,
+
+    // The ID from the most recent GOAWAY frame, set by the control stream reader.
+    goaway: watch::Receiver<Option<VarInt>>,
+
+    // Session-level flow control, shared with every stream.
+    flow: Arc<FlowControl>,
+
+    // Datagram options and the DATAGRAM capsules sent and received on the CONNECT stream.
+    datagrams: Arc<Datagrams>,
+
+    // Whether the peer enabled HTTP/3 datagrams in its SETTINGS, always true for raw QUIC.
+    peer_datagrams: bool,
 }
 
 impl Session {
-    pub(crate) fn new(conn: quinn::Connection, settings: Settings, connect: Connect) -> Self {
+    pub(crate) fn new(
+        conn: quinn::Connection,
+        mut settings: Settings,
+        mut connect: Connect,
+    ) -> Self {
         // The session ID is the stream ID of the CONNECT request.
         let session_id = connect.session_id();
 
@@ -68,75 +100,237 @@ impl Session {
         let mut header_datagram = Vec::new();
         session_id.encode(&mut header_datagram);
 
-        // Accept logic is stateful, so use an Arc<Mutex> to share it.
-        let accept = SessionAccept::new(conn.clone(), session_id);
+        let counters = Arc::new(SessionCounters::default());
+
+        // Flow control capsules are written to the CONNECT stream by the background task.
+        let (capsules, capsules_rx) = mpsc::unbounded_channel();
+        let flow = Arc::new(FlowControl::new(
+            conn.clone(),
+            connect.limits(),
+            settings.remote(),
+            capsules,
+        ));
+
+        let (datagrams, datagrams_task) = Datagrams::new(connect.limits());
+        let peer_datagrams = settings.remote().supports_datagrams();
+
+        // Deliver any datagrams that arrived before the CONNECT request, along with the capsule datagrams.
+        for datagram in connect.take_datagrams() {
+            match Self::strip_session_id(datagram, Some(session_id)) {
+                Ok(payload) => {
+                    let policy = datagrams.policy();
+                    datagrams_task.incoming.push(payload, policy);
+                }
+                Err(err) => log::debug!("dropping early datagram: {err}"),
+            }
+        }
+
+        // Everything created below (including background tasks) inherits this span.
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "session",
+            remote = %conn.remote_address(),
+            session_id = %session_id,
+            url = %connect.url(),
+        );
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+
+        // Accept logic is stateful, so use an Arc to share it.
+        let qlog = connect.qlog().clone();
+        let default_group = SendGroup::unranked();
+        let accept = SessionAccept::new(
+            conn.clone(),
+            session_id,
+            counters.clone(),
+            default_group.clone(),
+            flow.clone(),
+            qlog.clone(),
+            connect.limits().clone(),
+            settings.take_qpack(),
+            settings.take_streams(),
+            connect.take_streams(),
+        );
+
+        // Read the peer's control stream in the background for the lifetime of the connection.
+        let goaway = match settings.take_control() {
+            Some((recv, remaining)) => {
+                let (control, goaway) =
+                    Control::new(conn.clone(), recv, remaining, connect.limits().clone());
+                let control = control.run();
+
+                #[cfg(feature = "tracing")]
+                let control = tracing::Instrument::in_current_span(control);
+
+                tokio::spawn(control);
+                goaway
+            }
+            None => watch::channel(None).1,
+        };
 
         let this = Self {
             conn,
-            accept: Some(Arc::new(Mutex::new(accept))),
+            accept: Some(Arc::new(accept)),
             session_id: Some(session_id),
-            header_uni,
-            header_bi,
+            header_uni: header_uni.into(),
+            header_bi: header_bi.into(),
             header_datagram,
             url: connect.url().clone(),
             settings: Some(Arc::new(settings)),
+            counters,
+            default_group,
+            qlog,
+            goaway,
+            flow,
+            datagrams: Arc::new(datagrams),
+            peer_datagrams,
         };
 
         // Run a background task to check if the connect stream is closed.
         let mut this2 = this.clone();
-        tokio::spawn(async move {
-            let (code, reason) = this2.run_closed(connect).await;
-            this2.close(code, reason.as_bytes());
-        });
+        let closed = async move {
+            let (code, reason) = this2.run_closed(connect, capsules_rx, datagrams_task).await;
+
+            log::debug!("session closed: code={code} reason={reason}");
+
+            // Nothing to record if the connection was already closed.
+            if this2.conn.close_reason().is_none() {
+                this2.qlog.session_closed(Owner::Remote, code, &reason);
+            }
+
+            this2.close_inner(code, reason.as_bytes());
+        };
+
+        #[cfg(feature = "tracing")]
+        let closed = tracing::Instrument::in_current_span(closed);
+
+        tokio::spawn(closed);
 
         this
     }
 
-    // Keep reading from the control stream until it's closed.
-    async fn run_closed(&mut self, connect: Connect) -> (u32, String) {
-        let (_send, mut recv) = connect.into_inner();
+    // Keep reading from the control stream until it's closed, writing any capsules we need to send.
+    async fn run_closed(
+        &mut self,
+        connect: Connect,
+        mut capsules: mpsc::UnboundedReceiver<web_transport_proto::Capsule>,
+        datagrams: DatagramsTask,
+    ) -> (u32, String) {
+        let limits = connect.limits().clone();
 
-        let mut buf = Vec::new();
+        // Start with any capsules that arrived along with the CONNECT request or response.
+        let (mut send, mut recv, remaining) = connect.into_inner();
+        let mut buf = remaining.to_vec();
 
         loop {
-            // Keep reading from the stream until we get a closed capsule.
-            match recv.read_buf(&mut buf).await {
-                Ok(0) => return (0, "".to_string()),
-                Ok(_) => {}
-                // std::io::Error is pretty useless
-                Err(_err) => return (1, "read error".to_string()),
-            };
+            // Decode every capsule in the buffer before reading more.
+            loop {
+                let mut cursor = Cursor::new(&buf);
+
+                match web_transport_proto::Capsule::decode(&mut cursor) {
+                    Ok(capsule) => {
+                        self.qlog.capsule(
+                            Owner::Remote,
+                            recv.id(),
+                            capsule.typ(),
+                            cursor.position() as usize,
+                        );
+
+                        match capsule {
+                            web_transport_proto::Capsule::CloseWebTransportSession {
+                                code,
+                                reason,
+                            } => return (code, reason),
+                            web_transport_proto::Capsule::Datagram { payload } => {
+                                // Datagrams are unreliable, so drop them if the application isn't reading fast enough.
+                                let policy = self.datagrams.policy();
+                                if datagrams.incoming.push(payload, policy) != Push::Queued {
+                                    log::debug!("dropping DATAGRAM capsule");
+                                    self.counters.datagram_dropped_received();
+                                }
+                            }
+                            web_transport_proto::Capsule::Unknown { typ, payload } => {
+                                log::warn!("unknown capsule: type={typ} size={}", payload.len());
+                            }
+                            capsule => self.flow.recv_capsule(&capsule),
+                        }
+                    }
+                    Err(web_transport_proto::CapsuleError::UnexpectedEnd) => break, // More data needed.
+                    Err(err) => {
+                        log::warn!("control stream capsule error: {err:?}");
+                        return (1, "capsule error".to_string());
+                    }
+                };
 
-            let mut cursor = Cursor::new(&buf);
+                let size = cursor.position() as usize;
+                buf.drain(..size);
+            }
+
+            // Don't let the peer make us buffer forever.
+            if buf.len() > limits.max_capsule_size {
+                let code = web_transport_proto::H3_EXCESSIVE_LOAD.try_into().unwrap();
+                self.conn.close(code, b"capsule too large");
+                return (1, "capsule too large".to_string());
+            }
 
-            match web_transport_proto::Capsule::decode(&mut cursor) {
-                Ok(capsule) => match capsule {
-                    web_transport_proto::Capsule::CloseWebTransportSession { code, reason } => {
-                        return (code, reason)
+            // Keep writing capsules until there's more to read.
+            loop {
+                tokio::select! {
+                    // Keep reading from the stream until we get a closed capsule.
+                    res = recv.read_buf(&mut buf) => match res {
+                        Ok(0) => return (0, "".to_string()),
+                        Ok(_) => break,
+                        // std::io::Error is pretty useless
+                        Err(_err) => return (1, "read error".to_string()),
+                    },
+                    Some(capsule) = capsules.recv() => {
+                        self.write_capsule(&mut send, capsule).await;
                     }
-                    web_transport_proto::Capsule::Unknown { typ, payload } => {
-                        log::warn!("unknown capsule: type={typ} size={}", payload.len());
+                    Some(payload) = datagrams.outgoing.pop() => {
+                        let capsule = web_transport_proto::Capsule::Datagram { payload };
+                        self.write_capsule(&mut send, capsule).await;
                     }
-                },
-                Err(web_transport_proto::CapsuleError::UnexpectedEnd) => continue, // More data needed.
-                Err(err) => {
-                    log::warn!("control stream capsule error: {err:?}");
-                    return (1, "capsule error".to_string());
                 }
-            };
+            }
+        }
+    }
 
-            buf.drain(..cursor.position() as usize);
+    async fn write_capsule(
+        &self,
+        send: &mut quinn::SendStream,
+        capsule: web_transport_proto::Capsule,
+    ) {
+        let mut encoded = Vec::new();
+        capsule.encode(&mut encoded);
+
+        // Ignore errors, as they'll be caught by the read side.
+        if send.write_all(&encoded).await.is_ok() {
+            self.qlog
+                .capsule(Owner::Local, send.id(), capsule.typ(), encoded.len());
         }
     }
 
     /// Connect using an established QUIC connection if you want to create the connection yourself.
     /// This will only work with a brand new QUIC connection using the HTTP/3 ALPN.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "client", skip_all, fields(remote = %conn.remote_address(), url = %url), err)
+    )]
     pub async fn connect(conn: quinn::Connection, url: Url) -> Result<Session, ClientError> {
+        Self::connect_with(conn, url, Qlog::default(), Limits::default()).await
+    }
+
+    pub(crate) async fn connect_with(
+        conn: quinn::Connection,
+        url: Url,
+        qlog: Qlog,
+        limits: Limits,
+    ) -> Result<Session, ClientError> {
         // Perform the H3 handshake by sending/reciving SETTINGS frames.
-        let settings = Settings::connect(&conn).await?;
+        let settings = Settings::connect(&conn, &qlog, &limits, false).await?;
 
         // Send the HTTP/3 CONNECT request.
-        let connect = Connect::open(&conn, url).await?;
+        let connect = Connect::open(&conn, url, qlog, limits).await?;
 
         // Return the resulting session with a reference to the control/connect streams.
         // If either stream is closed, then the session will be closed, so we need to keep them around.
@@ -148,32 +342,36 @@ impl Session {
     /// Accept a new unidirectional stream. See [`quinn::Connection::accept_uni`].
     pub async fn accept_uni(&self) -> Result<RecvStream, SessionError> {
         if let Some(accept) = &self.accept {
-            poll_fn(|cx| accept.lock().unwrap().poll_accept_uni(cx)).await
+            accept.accept_uni().await
         } else {
-            self.conn
-                .accept_uni()
-                .await
-                .map(RecvStream::new)
-                .map_err(Into::into)
+            let recv = self.conn.accept_uni().await?;
+            let credit = StreamCredit::remote(self.flow.clone(), false);
+            Ok(RecvStream::new(recv, self.counters.open_uni(), credit))
         }
     }
 
     /// Accept a new bidirectional stream. See [`quinn::Connection::accept_bi`].
     pub async fn accept_bi(&self) -> Result<(SendStream, RecvStream), SessionError> {
         if let Some(accept) = &self.accept {
-            poll_fn(|cx| accept.lock().unwrap().poll_accept_bi(cx)).await
+            accept.accept_bi().await
         } else {
-            self.conn
-                .accept_bi()
-                .await
-                .map(|(send, recv)| (SendStream::new(send), RecvStream::new(recv)))
-                .map_err(Into::into)
+            let (send, recv) = self.conn.accept_bi().await?;
+            let open = self.counters.open_bi();
+            let credit = StreamCredit::remote(self.flow.clone(), true);
+            Ok((
+                SendStream::new(send, open.clone(), credit.clone(), &self.default_group),
+                RecvStream::new(recv, open, credit),
+            ))
         }
     }
 
     /// Open a new unidirectional stream. See [`quinn::Connection::open_uni`].
+    ///
+    /// This blocks until the peer's session flow control limit allows another stream.
     pub async fn open_uni(&self) -> Result<SendStream, SessionError> {
+        let credit = poll_fn(|cx| self.flow.poll_open(cx, false)).await;
         let mut send = self.conn.open_uni().await?;
+        credit.opened();
 
         // Set the stream priority to max and then write the stream header.
         // Otherwise the application could write data with lower priority than the header, resulting in queuing.
@@ -183,12 +381,23 @@ impl Session {
 
         // Reset the stream priority back to the default of 0.
         send.set_priority(0).ok();
-        Ok(SendStream::new(send))
+
+        let credit = StreamCredit::local(self.flow.clone());
+        Ok(SendStream::new(
+            send,
+            self.counters.open_uni(),
+            credit,
+            &self.default_group,
+        ))
     }
 
     /// Open a new bidirectional stream. See [`quinn::Connection::open_bi`].
+    ///
+    /// This blocks until the peer's session flow control limit allows another stream.
     pub async fn open_bi(&self) -> Result<(SendStream, RecvStream), SessionError> {
+        let credit = poll_fn(|cx| self.flow.poll_open(cx, true)).await;
         let (mut send, recv) = self.conn.open_bi().await?;
+        credit.opened();
 
         // Set the stream priority to max and then write the stream header.
         // Otherwise the application could write data with lower priority than the header, resulting in queuing.
@@ -198,7 +407,53 @@ impl Session {
 
         // Reset the stream priority back to the default of 0.
         send.set_priority(0).ok();
-        Ok((SendStream::new(send), RecvStream::new(recv)))
+
+        let open = self.counters.open_bi();
+        let credit = StreamCredit::local(self.flow.clone());
+        Ok((
+            SendStream::new(send, open.clone(), credit.clone(), &self.default_group),
+            RecvStream::new(recv, open, credit),
+        ))
+    }
+
+    /// Create a new [SendGroup], which streams can join with [`SendStream::set_send_group`].
+    ///
+    /// Each group gets a fair share of bandwidth, while streams within a group are sent in priority order.
+    pub fn send_group(&self) -> SendGroup {
+        SendGroup::new()
+    }
+
+    /// Open a new unidirectional stream and write the initial payload.
+    ///
+    /// The stream header and payload are written together with [`quinn::SendStream::write_chunks`], without copying.
+    /// This avoids a separate write (and possibly a packet) for the header, useful when sending one message per stream.
+    pub async fn open_uni_with(&self, data: Bytes) -> Result<SendStream, WriteError> {
+        let credit = poll_fn(|cx| self.flow.poll_open(cx, false)).await;
+        let send = self.conn.open_uni().await.map_err(SessionError::from)?;
+        credit.opened();
+
+        let credit = StreamCredit::local(self.flow.clone());
+        let mut send = SendStream::new(send, self.counters.open_uni(), credit, &self.default_group);
+        send.write_with_header(self.header_uni.clone(), data)
+            .await?;
+
+        Ok(send)
+    }
+
+    /// Open a new bidirectional stream and write the initial payload.
+    ///
+    /// See [Self::open_uni_with] for details.
+    pub async fn open_bi_with(&self, data: Bytes) -> Result<(SendStream, RecvStream), WriteError> {
+        let credit = poll_fn(|cx| self.flow.poll_open(cx, true)).await;
+        let (send, recv) = self.conn.open_bi().await.map_err(SessionError::from)?;
+        credit.opened();
+
+        let open = self.counters.open_bi();
+        let credit = StreamCredit::local(self.flow.clone());
+        let mut send = SendStream::new(send, open.clone(), credit.clone(), &self.default_group);
+        send.write_with_header(self.header_bi.clone(), data).await?;
+
+        Ok((send, RecvStream::new(recv, open, credit)))
     }
 
     /// Asynchronously receives an application datagram from the remote peer.
@@ -206,12 +461,33 @@ impl Session {
     /// This method is used to receive an application datagram sent by the remote
     /// peer over the connection.
     /// It waits for a datagram to become available and returns the received bytes.
+    /// This includes datagrams sent by the peer as DATAGRAM capsules, see [`Self::set_datagram_fallback`].
     pub async fn read_datagram(&self) -> Result<Bytes, SessionError> {
-        let mut datagram = self.conn.read_datagram().await?;
+        let datagram = tokio::select! {
+            // Queued datagrams go first, since they include any that arrived before the session was established.
+            biased;
+            // Stops matching once the CONNECT stream is closed.
+            Some(datagram) = self.datagrams.incoming.pop() => datagram,
+            res = self.read_quic_datagram() => res?,
+        };
+
+        self.counters.datagram_received();
 
+        Ok(datagram)
+    }
+
+    async fn read_quic_datagram(&self) -> Result<Bytes, SessionError> {
+        let datagram = self.conn.read_datagram().await?;
+        Self::strip_session_id(datagram, self.session_id)
+    }
+
+    fn strip_session_id(
+        mut datagram: Bytes,
+        session_id: Option<VarInt>,
+    ) -> Result<Bytes, SessionError> {
         let mut cursor = Cursor::new(&datagram);
 
-        if let Some(session_id) = self.session_id {
+        if let Some(session_id) = session_id {
             // We have to check and strip the session ID from the datagram.
             let actual_id = VarInt::decode(&mut cursor).map_err(|_| {
                 WebTransportError::ReadError(quinn::ReadExactError::FinishedEarly(0))
@@ -231,19 +507,176 @@ impl Session {
     ///
     /// Datagrams are unreliable and may be dropped or delivered out of order.
     /// The data must be smaller than [`max_datagram_size`](Self::max_datagram_size).
-    pub fn send_datagram(&self, data: Bytes) -> Result<(), SessionError> {
-        if !self.header_datagram.is_empty() {
-            // Unfortunately, we need to allocate/copy each datagram because of the Quinn API.
-            // Pls go +1 if you care: https://github.com/quinn-rs/quinn/issues/1724
-            let mut buf = BytesMut::with_capacity(self.header_datagram.len() + data.len());
+    ///
+    /// If the send buffer is full, a datagram is dropped according to the [drop policy](Self::set_datagram_drop_policy).
+    /// Use [`Self::send_datagram_wait`] to wait for space instead.
+    ///
+    /// If [datagram fallback](Self::set_datagram_fallback) is enabled, datagrams that can't be sent via QUIC are sent as DATAGRAM capsules instead.
+    pub fn send_datagram(&self, data: Bytes) -> Result<(), SendDatagramError> {
+        let res = self.send_datagram_inner(OutgoingDatagram::Payload(data));
+        if res.is_err() {
+            self.counters.datagram_failed();
+        }
 
-            // Prepend the datagram with the header indicating the session ID.
-            buf.extend_from_slice(&self.header_datagram);
-            buf.extend_from_slice(&data);
+        res
+    }
 
-            self.conn.send_datagram(buf.into())?;
-        } else {
-            self.conn.send_datagram(data)?;
+    /// Returns the number of bytes to reserve at the front of [`Self::send_datagram_with_headroom`] buffers.
+    ///
+    /// This is the size of the session ID that prefixes each QUIC datagram, or 0 for raw QUIC sessions.
+    pub fn datagram_headroom(&self) -> usize {
+        self.header_datagram.len()
+    }
+
+    /// Sends an application datagram like [`Self::send_datagram`], but without copying the payload.
+    ///
+    /// The first [`Self::datagram_headroom`] bytes of `buf` are reserved for the session ID and overwritten, and the rest is the payload.
+    /// For example, create the buffer with `BytesMut::zeroed(headroom)` and then append the payload.
+    pub fn send_datagram_with_headroom(&self, mut buf: BytesMut) -> Result<(), SendDatagramError> {
+        let headroom = self.header_datagram.len();
+        if buf.len() < headroom {
+            self.counters.datagram_failed();
+            return Err(SendDatagramError::MissingHeadroom(headroom));
+        }
+
+        buf[..headroom].copy_from_slice(&self.header_datagram);
+
+        let res = self.send_datagram_inner(OutgoingDatagram::Prefixed(buf.freeze(), headroom));
+        if res.is_err() {
+            self.counters.datagram_failed();
+        }
+
+        res
+    }
+
+    fn send_datagram_inner(&self, data: OutgoingDatagram) -> Result<(), SendDatagramError> {
+        if self.use_capsule(data.len()) {
+            self.check_capsule_size(data.len())?;
+            let res = self
+                .datagrams
+                .outgoing
+                .push(data.into_payload(), self.datagrams.policy());
+            return self.pushed(res);
+        }
+
+        // Quinn can't tell if the peer enabled HTTP/3 datagrams, only QUIC datagrams.
+        if !self.peer_datagrams {
+            return Err(SendDatagramError::UnsupportedByPeer);
+        }
+
+        let full = self.conn.datagram_send_buffer_space() < self.header_datagram.len() + data.len();
+        if full && self.datagrams.policy() == DatagramDropPolicy::DropNewest {
+            return self.pushed(Push::Dropped);
+        }
+
+        self.conn
+            .send_datagram(self.quic_datagram(data))
+            .map_err(|err| self.datagram_error(err))?;
+        self.counters.datagram_sent();
+
+        // Quinn silently evicted the oldest datagram to make room, but only if the send succeeded.
+        // It may have evicted more than one, but the change in buffer space is in bytes and races with transmits, so count one.
+        if full {
+            self.counters.datagram_dropped();
+        }
+
+        Ok(())
+    }
+
+    /// Sends an application datagram to the remote peer, waiting for space in the send buffer.
+    ///
+    /// Unlike [`Self::send_datagram`], this never drops a datagram locally, so it can be used for backpressure.
+    /// The datagram may still be lost in transit.
+    pub async fn send_datagram_wait(&self, data: Bytes) -> Result<(), SendDatagramError> {
+        let res = self.send_datagram_wait_inner(data).await;
+        if res.is_err() {
+            self.counters.datagram_failed();
+        }
+
+        res
+    }
+
+    async fn send_datagram_wait_inner(&self, data: Bytes) -> Result<(), SendDatagramError> {
+        if self.use_capsule(data.len()) {
+            self.check_capsule_size(data.len())?;
+            let res = self.datagrams.outgoing.push_wait(data).await;
+            return self.pushed(res);
+        }
+
+        if !self.peer_datagrams {
+            return Err(SendDatagramError::UnsupportedByPeer);
+        }
+
+        self.conn
+            .send_datagram_wait(self.quic_datagram(OutgoingDatagram::Payload(data)))
+            .await
+            .map_err(|err| self.datagram_error(err))?;
+        self.counters.datagram_sent();
+
+        Ok(())
+    }
+
+    // Prepend the session ID to a QUIC datagram, unless it's already there.
+    fn quic_datagram(&self, data: OutgoingDatagram) -> Bytes {
+        let data = match data {
+            OutgoingDatagram::Prefixed(buf, _) => return buf,
+            OutgoingDatagram::Payload(data) if self.header_datagram.is_empty() => return data,
+            OutgoingDatagram::Payload(data) => data,
+        };
+
+        // Unfortunately, we need to allocate/copy each datagram because of the Quinn API.
+        // Pls go +1 if you care: https://github.com/quinn-rs/quinn/issues/1724
+        // Use send_datagram_with_headroom to avoid this.
+        let mut buf = BytesMut::with_capacity(self.header_datagram.len() + data.len());
+
+        // Prepend the datagram with the header indicating the session ID.
+        buf.extend_from_slice(&self.header_datagram);
+        buf.extend_from_slice(&data);
+
+        buf.into()
+    }
+
+    fn datagram_error(&self, err: quinn::SendDatagramError) -> SendDatagramError {
+        match err {
+            quinn::SendDatagramError::UnsupportedByPeer => SendDatagramError::UnsupportedByPeer,
+            quinn::SendDatagramError::Disabled => SendDatagramError::Disabled,
+            quinn::SendDatagramError::TooLarge => {
+                SendDatagramError::TooLarge(self.max_datagram_size())
+            }
+            quinn::SendDatagramError::ConnectionLost(err) => SendDatagramError::SessionClosed(err),
+        }
+    }
+
+    // Returns true if the datagram should be sent as a capsule because it can't be sent via QUIC.
+    fn use_capsule(&self, size: usize) -> bool {
+        let fits = self.quic_datagram_size().is_some_and(|max| size <= max);
+        self.datagrams.fallback() && !fits
+    }
+
+    fn check_capsule_size(&self, size: usize) -> Result<(), SendDatagramError> {
+        match size > web_transport_proto::MAX_DATAGRAM_SIZE {
+            true => Err(SendDatagramError::TooLarge(self.max_datagram_size())),
+            false => Ok(()),
+        }
+    }
+
+    // Update the counters after queuing a DATAGRAM capsule.
+    fn pushed(&self, res: Push) -> Result<(), SendDatagramError> {
+        match res {
+            Push::Queued => self.counters.datagram_sent(),
+            Push::Evicted => {
+                self.counters.datagram_sent();
+                self.counters.datagram_dropped();
+            }
+            Push::Dropped => self.counters.datagram_dropped(),
+            // The CONNECT stream is closed, so the session is too.
+            Push::Closed => {
+                if let Some(err) = self.conn.close_reason() {
+                    return Err(SendDatagramError::SessionClosed(err));
+                }
+
+                self.counters.datagram_dropped();
+            }
         }
 
         Ok(())
@@ -251,16 +684,70 @@ impl Session {
 
     /// Computes the maximum size of datagrams that may be passed to
     /// [`send_datagram`](Self::send_datagram).
+    ///
+    /// If [datagram fallback](Self::set_datagram_fallback) is enabled, this is the larger of the QUIC and capsule limits.
+    /// Otherwise, this returns 0 if the peer doesn't support datagrams, see [`Self::datagrams_enabled`].
     pub fn max_datagram_size(&self) -> usize {
-        let mtu = self
-            .conn
-            .max_datagram_size()
-            .expect("datagram support is required");
-        mtu.saturating_sub(self.header_datagram.len())
+        let quic = self.quic_datagram_size().unwrap_or(0);
+
+        match self.datagrams.fallback() {
+            true => quic.max(web_transport_proto::MAX_DATAGRAM_SIZE),
+            false => quic,
+        }
+    }
+
+    // The maximum payload of a QUIC datagram, or None if they can't be used.
+    fn quic_datagram_size(&self) -> Option<usize> {
+        if !self.peer_datagrams {
+            return None;
+        }
+
+        let mtu = self.conn.max_datagram_size()?;
+        Some(mtu.saturating_sub(self.header_datagram.len()))
+    }
+
+    /// Returns true if the peer enabled HTTP/3 datagrams in its SETTINGS and QUIC datagrams in its transport parameters.
+    ///
+    /// Otherwise, [`Self::send_datagram`] fails unless [datagram fallback](Self::set_datagram_fallback) is enabled.
+    pub fn datagrams_enabled(&self) -> bool {
+        self.quic_datagram_size().is_some()
+    }
+
+    /// Send datagrams as DATAGRAM capsules on the CONNECT stream when they can't be sent via QUIC.
+    ///
+    /// This happens when the peer disabled QUIC datagrams or the datagram is larger than the path allows.
+    /// Capsule datagrams are reliable and ordered, so they may be delayed by packet loss.
+    /// Disabled by default, and applies to all clones of this session. This does nothing for raw QUIC sessions.
+    pub fn set_datagram_fallback(&self, enabled: bool) {
+        self.datagrams.set_fallback(enabled);
+    }
+
+    /// Choose which datagram to drop when the send buffer or the DATAGRAM capsule receive queue is full.
+    ///
+    /// Defaults to [`DatagramDropPolicy::DropOldest`] and applies to all clones of this session.
+    /// Quinn's receive buffer always drops the oldest datagram, see [`quinn::TransportConfig::datagram_receive_buffer_size`].
+    pub fn set_datagram_drop_policy(&self, policy: DatagramDropPolicy) {
+        self.datagrams.set_policy(policy);
+    }
+
+    /// Returns a snapshot of the session statistics. See [`SessionStats`].
+    ///
+    /// Unlike [`quinn::Connection::stats`], this is a stable API that also tracks WebTransport datagrams and streams.
+    pub fn stats(&self) -> SessionStats {
+        SessionStats::new(self.conn.stats(), &self.counters)
     }
 
     /// Immediately close the connection with an error code and reason. See [`quinn::Connection::close`].
     pub fn close(&self, code: u32, reason: &[u8]) {
+        if self.conn.close_reason().is_none() {
+            let reason = String::from_utf8_lossy(reason);
+            self.qlog.session_closed(Owner::Local, code, &reason);
+        }
+
+        self.close_inner(code, reason);
+    }
+
+    fn close_inner(&self, code: u32, reason: &[u8]) {
         let code = if self.session_id.is_some() {
             web_transport_proto::error_to_http3(code)
                 .try_into()
@@ -304,12 +791,36 @@ impl Session {
             accept: None,
             settings: None,
             url,
+            counters: Default::default(),
+            default_group: SendGroup::unranked(),
+            qlog: Default::default(),
+            goaway: watch::channel(None).1,
+            flow: Arc::new(FlowControl::disabled()),
+            datagrams: Arc::new(Datagrams::raw()),
+            peer_datagrams: true,
         }
     }
 
     pub fn url(&self) -> &Url {
         &self.url
     }
+
+    /// Returns true if the peer sent a GOAWAY frame, indicating it won't accept new requests.
+    ///
+    /// The session remains usable, but you should start migrating to a new connection.
+    pub fn is_goaway(&self) -> bool {
+        self.goaway.borrow().is_some()
+    }
+
+    /// Block until the peer sends a GOAWAY frame.
+    ///
+    /// This never returns for raw QUIC sessions, or if the connection is closed first.
+    pub async fn goaway(&self) {
+        let mut goaway = self.goaway.clone();
+        if goaway.wait_for(Option::is_some).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
 }
 
 impl Deref for Session {
@@ -334,202 +845,428 @@ impl PartialEq for Session {
 
 impl Eq for Session {}
 
-// Type aliases just so clippy doesn't complain about the complexity.
-type AcceptUni = dyn Stream<Item = Result<quinn::RecvStream, quinn::ConnectionError>> + Send;
-type AcceptBi = dyn Stream<Item = Result<(quinn::SendStream, quinn::RecvStream), quinn::ConnectionError>>
-    + Send;
-type PendingUni = dyn Future<Output = Result<(StreamUni, quinn::RecvStream), SessionError>> + Send;
-type PendingBi = dyn Future<Output = Result<Option<(quinn::SendStream, quinn::RecvStream)>, SessionError>>
-    + Send;
+// An outgoing datagram payload, which may already be prefixed with the session ID.
+enum OutgoingDatagram {
+    Payload(Bytes),
+
+    // The session ID was written into the first N bytes.
+    Prefixed(Bytes, usize),
+}
+
+impl OutgoingDatagram {
+    fn len(&self) -> usize {
+        match self {
+            Self::Payload(data) => data.len(),
+            Self::Prefixed(buf, headroom) => buf.len() - headroom,
+        }
+    }
+
+    // DATAGRAM capsules don't include the session ID.
+    fn into_payload(self) -> Bytes {
+        match self {
+            Self::Payload(data) => data,
+            Self::Prefixed(buf, headroom) => buf.slice(headroom..),
+        }
+    }
+}
 
 // Logic just for accepting streams, which is annoying because of the stream header.
-pub struct SessionAccept {
-    session_id: VarInt,
+//
+// This is poll-based so streams are only accepted from QUIC while the application is waiting in accept,
+// which keeps QUIC stream credit as backpressure. Each header is decoded by a small state machine stored in a slab.
+pub(crate) struct SessionAccept {
+    acceptor: Acceptor,
+
+    // Each direction has its own lock so accept_uni and accept_bi don't contend.
+    // The lock is only held while polling, never across an await.
+    uni: Mutex<AcceptUni>,
+    bi: Mutex<AcceptBi>,
+
+    // The session span, entered while polling so stream events are attributed to the session.
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+// Quinn's accept futures borrow the connection, so they're wrapped in a stream that owns a clone.
+// This is allocated once per session, not per stream.
+type Incoming<T> = Pin<Box<dyn Stream<Item = Result<T, quinn::ConnectionError>> + Send>>;
+
+struct AcceptUni {
+    incoming: Incoming<quinn::RecvStream>,
+
+    // Streams that are still reading their header, including any buffered streams.
+    // Most headers arrive along with the stream, so this is usually empty.
+    pending: PendingSet<PendingUni>,
 
     // We also need to keep a reference to the qpack streams if the endpoint (incorrectly) creates them.
     // Again, this is just so they don't get closed until we drop the session.
-    qpack_encoder: Option<quinn::RecvStream>,
-    qpack_decoder: Option<quinn::RecvStream>,
+    qpack: QpackStreams,
+
+    waiters: Arc<Waiters>,
+}
 
-    accept_uni: Pin<Box<AcceptUni>>,
-    accept_bi: Pin<Box<AcceptBi>>,
+struct AcceptBi {
+    incoming: Incoming<(quinn::SendStream, quinn::RecvStream)>,
+    pending: PendingSet<PendingBi>,
+    waiters: Arc<Waiters>,
+}
 
-    // Keep track of work being done to read/write the WebTransport stream header.
-    pending_uni: FuturesUnordered<Pin<Box<PendingUni>>>,
-    pending_bi: FuturesUnordered<Pin<Box<PendingBi>>>,
+// The tasks waiting in accept, which share the same accept future and pending streams.
+// Everything is polled with a waker that wakes all of them, so concurrent callers don't miss a stream.
+#[derive(Default)]
+struct Waiters {
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Waiters {
+    // Register the caller and return the context used to poll the shared state.
+    fn register(self: &Arc<Self>, waker: &Waker) -> Waker {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+
+        Waker::from(self.clone())
+    }
+}
+
+impl Wake for Waiters {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap());
+        wakers.into_iter().for_each(Waker::wake);
+    }
 }
 
 impl SessionAccept {
-    pub(crate) fn new(conn: quinn::Connection, session_id: VarInt) -> Self {
-        // Create a stream that just outputs new streams, so it's easy to call from poll.
-        let accept_uni = Box::pin(futures::stream::unfold(conn.clone(), |conn| async {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        conn: quinn::Connection,
+        session_id: VarInt,
+        counters: Arc<SessionCounters>,
+        default_group: SendGroup,
+        flow: Arc<FlowControl>,
+        qlog: Qlog,
+        limits: Limits,
+        qpack: QpackStreams,
+        mut streams_uni: Vec<PendingUni>,
+        mut streams_bi: Vec<PendingBi>,
+    ) -> Self {
+        let incoming_uni = Box::pin(futures::stream::unfold(conn.clone(), |conn| async {
             Some((conn.accept_uni().await, conn))
         }));
 
-        let accept_bi = Box::pin(futures::stream::unfold(conn, |conn| async {
+        let incoming_bi = Box::pin(futures::stream::unfold(conn.clone(), |conn| async {
             Some((conn.accept_bi().await, conn))
         }));
 
-        Self {
-            session_id,
-
-            qpack_decoder: None,
-            qpack_encoder: None,
+        // Finish decoding any streams that arrived during the handshake, in the order they were opened.
+        let deadline = Instant::now() + limits.stream_header_timeout;
+        streams_uni
+            .iter_mut()
+            .for_each(|s| s.set_deadline(deadline));
+        streams_bi.iter_mut().for_each(|s| s.set_deadline(deadline));
+
+        // Buffered streams are decoded along with new ones, so a slow header doesn't block anything else.
+        // They're inserted first and polled in order, so they're returned in the order they were opened unless a header is delayed.
+        let mut uni = AcceptUni {
+            incoming: incoming_uni,
+            pending: PendingSet::new(),
+            qpack,
+            waiters: Default::default(),
+        };
+        streams_uni.into_iter().for_each(|s| uni.pending.insert(s));
 
-            accept_uni,
-            accept_bi,
+        let mut bi = AcceptBi {
+            incoming: incoming_bi,
+            pending: PendingSet::new(),
+            waiters: Default::default(),
+        };
+        streams_bi.into_iter().for_each(|s| bi.pending.insert(s));
 
-            pending_uni: FuturesUnordered::new(),
-            pending_bi: FuturesUnordered::new(),
+        Self {
+            acceptor: Acceptor {
+                conn,
+                session_id,
+                counters,
+                default_group,
+                flow,
+                qlog,
+                limits,
+            },
+            uni: Mutex::new(uni),
+            bi: Mutex::new(bi),
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
         }
     }
 
-    // This is poll-based because we accept and decode streams in parallel.
-    // In async land I would use tokio::JoinSet, but that requires a runtime.
-    // It's better to use FuturesUnordered instead because it's agnostic.
-    pub fn poll_accept_uni(
-        &mut self,
-        cx: &mut Context<'_>,
-    ) -> Poll<Result<RecvStream, SessionError>> {
-        loop {
-            // Accept any new streams.
-            if let Poll::Ready(Some(res)) = self.accept_uni.poll_next_unpin(cx) {
-                // Start decoding the header and add the future to the list of pending streams.
-                let recv = res?;
-                let pending = Self::decode_uni(recv, self.session_id);
-                self.pending_uni.push(Box::pin(pending));
+    pub async fn accept_uni(&self) -> Result<RecvStream, SessionError> {
+        poll_fn(|cx| self.poll_accept_uni(cx)).await
+    }
 
-                continue;
-            }
+    pub async fn accept_bi(&self) -> Result<(SendStream, RecvStream), SessionError> {
+        poll_fn(|cx| self.poll_accept_bi(cx)).await
+    }
 
-            // Poll the list of pending streams.
-            let (typ, recv) = match ready!(self.pending_uni.poll_next_unpin(cx)) {
-                Some(res) => res?,
-                None => return Poll::Pending,
-            };
+    fn poll_accept_uni(&self, cx: &mut Context<'_>) -> Poll<Result<RecvStream, SessionError>> {
+        #[cfg(feature = "tracing")]
+        let _enter = self.span.enter();
 
-            // Decide if we keep looping based on the type.
-            match typ {
-                StreamUni::WEBTRANSPORT => {
-                    let recv = RecvStream::new(recv);
+        let acceptor = &self.acceptor;
+        let mut accept = self.uni.lock().unwrap();
+        let accept = &mut *accept;
+
+        let waker = accept.waiters.register(cx.waker());
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            // Return the first stream to finish reading its header.
+            if let Poll::Ready((pending, res)) = accept.pending.poll_next(&mut cx, |pending, cx| {
+                pending.poll_header(cx, acceptor.session_id, &acceptor.qlog)
+            }) {
+                if let Some(recv) = acceptor.decode_uni(&mut accept.qpack, pending, res)? {
                     return Poll::Ready(Ok(recv));
                 }
-                StreamUni::QPACK_DECODER => {
-                    self.qpack_decoder = Some(recv);
-                }
-                StreamUni::QPACK_ENCODER => {
-                    self.qpack_encoder = Some(recv);
-                }
-                _ => {
-                    // ignore unknown streams
-                    log::debug!("ignoring unknown unidirectional stream: {typ:?}");
-                }
+
+                continue;
             }
-        }
-    }
 
-    // Reads the stream header, returning the stream type.
-    async fn decode_uni(
-        mut recv: quinn::RecvStream,
-        expected_session: VarInt,
-    ) -> Result<(StreamUni, quinn::RecvStream), SessionError> {
-        // Read the VarInt at the start of the stream.
-        let typ = Self::read_varint(&mut recv).await?;
-        let typ = StreamUni(typ);
+            // The stream never ends, since quinn keeps returning the connection error.
+            let Some(res) = ready!(accept.incoming.poll_next_unpin(&mut cx)) else {
+                unreachable!("accept stream ended");
+            };
+            let mut recv = res?;
 
-        if typ == StreamUni::WEBTRANSPORT {
-            // Read the session_id and validate it
-            let session_id = Self::read_varint(&mut recv).await?;
-            if session_id != expected_session {
-                return Err(WebTransportError::UnknownSession.into());
+            // Don't let the peer make us hold an unbounded number of streams.
+            if accept.pending.len() >= acceptor.limits.max_pending_streams {
+                log::debug!("too many pending unidirectional streams: id={}", recv.id());
+
+                Acceptor::reject_recv(&mut recv, web_transport_proto::H3_EXCESSIVE_LOAD);
+                acceptor.counters.stream_rejected();
+                continue;
             }
-        }
 
-        // We need to keep a reference to the qpack streams if the endpoint (incorrectly) creates them, so return everything.
-        Ok((typ, recv))
+            // The header usually arrives with the stream, so it's decoded on the next loop.
+            let deadline = Instant::now() + acceptor.limits.stream_header_timeout;
+            accept.pending.insert(PendingUni::new(recv, deadline));
+        }
     }
 
-    pub fn poll_accept_bi(
-        &mut self,
+    fn poll_accept_bi(
+        &self,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(SendStream, RecvStream), SessionError>> {
+        #[cfg(feature = "tracing")]
+        let _enter = self.span.enter();
+
+        let acceptor = &self.acceptor;
+        let mut accept = self.bi.lock().unwrap();
+        let accept = &mut *accept;
+
+        let waker = accept.waiters.register(cx.waker());
+        let mut cx = Context::from_waker(&waker);
+
         loop {
-            // Accept any new streams.
-            if let Poll::Ready(Some(res)) = self.accept_bi.poll_next_unpin(cx) {
-                // Start decoding the header and add the future to the list of pending streams.
-                let (send, recv) = res?;
-                let pending = Self::decode_bi(send, recv, self.session_id);
-                self.pending_bi.push(Box::pin(pending));
+            if let Poll::Ready((pending, res)) = accept.pending.poll_next(&mut cx, |pending, cx| {
+                pending.poll_header(cx, acceptor.session_id, &acceptor.qlog)
+            }) {
+                // Keep looping if it's a stream we want to ignore.
+                if let Some(stream) = acceptor.decode_bi(pending, res)? {
+                    return Poll::Ready(Ok(stream));
+                }
 
                 continue;
             }
 
-            // Poll the list of pending streams.
-            let res = match ready!(self.pending_bi.poll_next_unpin(cx)) {
-                Some(res) => res?,
-                None => return Poll::Pending,
+            let Some(res) = ready!(accept.incoming.poll_next_unpin(&mut cx)) else {
+                unreachable!("accept stream ended");
             };
+            let (mut send, mut recv) = res?;
 
-            if let Some((send, recv)) = res {
-                // Wrap the streams in our own types for correct error codes.
-                let send = SendStream::new(send);
-                let recv = RecvStream::new(recv);
-                return Poll::Ready(Ok((send, recv)));
+            // Don't let the peer make us hold an unbounded number of streams.
+            if accept.pending.len() >= acceptor.limits.max_pending_streams {
+                log::debug!("too many pending bidirectional streams: id={}", send.id());
+
+                Acceptor::reject_recv(&mut recv, web_transport_proto::H3_EXCESSIVE_LOAD);
+                Acceptor::reject_send(&mut send, web_transport_proto::H3_EXCESSIVE_LOAD);
+                acceptor.counters.stream_rejected();
+                continue;
             }
 
-            // Keep looping if it's a stream we want to ignore.
+            let deadline = Instant::now() + acceptor.limits.stream_header_timeout;
+            accept.pending.insert(PendingBi::new(send, recv, deadline));
         }
     }
 
-    // Reads the stream header, returning Some if it's a WebTransport stream.
-    async fn decode_bi(
-        send: quinn::SendStream,
-        mut recv: quinn::RecvStream,
-        expected_session: VarInt,
-    ) -> Result<Option<(quinn::SendStream, quinn::RecvStream)>, SessionError> {
-        let typ = Self::read_varint(&mut recv).await?;
-        if Frame(typ) != Frame::WEBTRANSPORT {
-            log::debug!("ignoring unknown bidirectional stream: {typ:?}");
-            return Ok(None);
-        }
+    // Reject a stream that arrived before the session was established, because the session was rejected.
+    // Only WebTransport streams get WT_BUFFERED_STREAM_REJECTED; we're just not reading the others.
+    pub(crate) fn reject_buffered_uni(stream: PendingUni) {
+        let code = match stream.typ() {
+            Some(StreamUni::WEBTRANSPORT) => web_transport_proto::WT_BUFFERED_STREAM_REJECTED,
+            // Unknown stream types, including grease.
+            Some(_) => web_transport_proto::H3_STREAM_CREATION_ERROR,
+            // The header hasn't arrived yet.
+            None => web_transport_proto::H3_NO_ERROR,
+        };
 
-        // Read the session ID and validate it.
-        let session_id = Self::read_varint(&mut recv).await?;
-        if session_id != expected_session {
-            return Err(WebTransportError::UnknownSession.into());
-        }
+        let mut recv = stream.into_stream();
+        Acceptor::reject_recv(&mut recv, code);
+    }
+
+    pub(crate) fn reject_buffered_bi(stream: PendingBi) {
+        let code = match stream.typ() {
+            Some(Frame::WEBTRANSPORT) => web_transport_proto::WT_BUFFERED_STREAM_REJECTED,
+            _ => web_transport_proto::H3_NO_ERROR,
+        };
 
-        Ok(Some((send, recv)))
+        let (mut send, mut recv) = stream.into_stream();
+        Acceptor::reject_recv(&mut recv, code);
+        Acceptor::reject_send(&mut send, code);
     }
+}
+
+// State shared by both directions.
+struct Acceptor {
+    conn: quinn::Connection,
+    session_id: VarInt,
+    counters: Arc<SessionCounters>,
+    default_group: SendGroup,
+    flow: Arc<FlowControl>,
+    qlog: Qlog,
+    limits: Limits,
+}
+
+impl Acceptor {
+    // Handles a stream that finished reading its header, returning Some if it's a WebTransport stream.
+    // Only connection errors are returned.
+    fn decode_uni(
+        &self,
+        qpack: &mut QpackStreams,
+        pending: PendingUni,
+        header: Result<StreamUni, SessionError>,
+    ) -> Result<Option<RecvStream>, SessionError> {
+        let typ = match header {
+            Ok(typ) => typ,
+            Err(SessionError::ConnectionError(err)) => return Err(err.into()),
+            Err(err) => {
+                let mut recv = pending.into_stream();
+
+                log::debug!(
+                    "rejecting unidirectional stream: id={} err={err}",
+                    recv.id()
+                );
+
+                Self::reject_recv(&mut recv, Self::reject_code(&err));
+                self.counters.stream_rejected();
+
+                return Ok(None);
+            }
+        };
+
+        let recv = pending.into_stream();
+
+        match typ {
+            StreamUni::WEBTRANSPORT => {
+                log::debug!("accepted unidirectional stream: id={}", recv.id());
+
+                // Close the session if the peer exceeded the stream limit.
+                if !self.flow.recv_stream(false) {
+                    return Ok(None);
+                }
 
-    // Read into the provided buffer and cast any errors to SessionError.
-    async fn read_full(recv: &mut quinn::RecvStream, buf: &mut [u8]) -> Result<(), SessionError> {
-        match recv.read_exact(buf).await {
-            Ok(()) => Ok(()),
-            Err(quinn::ReadExactError::ReadError(quinn::ReadError::ConnectionLost(err))) => {
-                Err(err.into())
+                let credit = StreamCredit::remote(self.flow.clone(), false);
+                let recv = RecvStream::new(recv, self.counters.open_uni(), credit);
+                return Ok(Some(recv));
+            }
+            StreamUni::QPACK_DECODER | StreamUni::QPACK_ENCODER => {
+                if !qpack.insert(typ, recv) {
+                    QpackStreams::duplicate(&self.conn);
+                }
+            }
+            StreamUni::CONTROL => {
+                // Only one control stream is allowed per connection.
+                log::warn!("duplicate control stream: id={}", recv.id());
+
+                let code = web_transport_proto::H3_STREAM_CREATION_ERROR;
+                self.conn
+                    .close(code.try_into().unwrap(), b"duplicate control stream");
+            }
+            _ => {
+                // ignore unknown streams
+                log::debug!(
+                    "ignoring unknown unidirectional stream: id={} type={typ:?}",
+                    recv.id()
+                );
             }
-            Err(err) => Err(WebTransportError::ReadError(err).into()),
         }
+
+        Ok(None)
     }
 
-    // Read a varint from the stream.
-    async fn read_varint(recv: &mut quinn::RecvStream) -> Result<VarInt, SessionError> {
-        // 8 bytes is the max size of a varint
-        let mut buf = [0; 8];
+    // Handles a stream that finished reading its header, returning Some if it's a WebTransport stream.
+    // Only connection errors are returned.
+    fn decode_bi(
+        &self,
+        pending: PendingBi,
+        header: Result<bool, SessionError>,
+    ) -> Result<Option<(SendStream, RecvStream)>, SessionError> {
+        let (mut send, mut recv) = pending.into_stream();
+
+        let err = match header {
+            Ok(true) => {
+                log::debug!("accepted bidirectional stream: id={}", send.id());
+
+                // Close the session if the peer exceeded the stream limit.
+                if !self.flow.recv_stream(true) {
+                    return Ok(None);
+                }
 
-        // Read the first byte because it includes the length.
-        Self::read_full(recv, &mut buf[0..1]).await?;
+                // Wrap the streams in our own types for correct error codes.
+                let open = self.counters.open_bi();
+                let credit = StreamCredit::remote(self.flow.clone(), true);
+                let send = SendStream::new(send, open.clone(), credit.clone(), &self.default_group);
+                let recv = RecvStream::new(recv, open, credit);
+                return Ok(Some((send, recv)));
+            }
+            Ok(false) => return Ok(None),
+            Err(SessionError::ConnectionError(err)) => return Err(err.into()),
+            Err(err) => err,
+        };
 
-        // 0b00 = 1, 0b01 = 2, 0b10 = 4, 0b11 = 8
-        let size = 1 << (buf[0] >> 6);
-        Self::read_full(recv, &mut buf[1..size]).await?;
+        log::debug!("rejecting bidirectional stream: id={} err={err}", send.id());
 
-        // Use a cursor to read the varint on the stack.
-        let mut cursor = Cursor::new(&buf[..size]);
-        let v = VarInt::decode(&mut cursor).unwrap();
+        let code = Self::reject_code(&err);
+        Self::reject_recv(&mut recv, code);
+        Self::reject_send(&mut send, code);
+        self.counters.stream_rejected();
 
-        Ok(v)
+        Ok(None)
+    }
+
+    // The HTTP/3 error code used to reject a stream with an invalid header.
+    fn reject_code(err: &SessionError) -> u64 {
+        match err {
+            SessionError::WebTransportError(WebTransportError::UnknownSession) => {
+                web_transport_proto::H3_ID_ERROR
+            }
+            _ => web_transport_proto::H3_REQUEST_INCOMPLETE,
+        }
+    }
+
+    // Send STOP_SENDING with an HTTP/3 error code.
+    fn reject_recv(recv: &mut quinn::RecvStream, code: u64) {
+        // Ignore the error if the stream is already closed.
+        recv.stop(code.try_into().unwrap()).ok();
+    }
+
+    // Send RESET_STREAM with an HTTP/3 error code.
+    fn reject_send(send: &mut quinn::SendStream, code: u64) {
+        // Ignore the error if the stream is already closed.
+        send.reset(code.try_into().unwrap()).ok();
     }
 }
 
@@ -563,7 +1300,7 @@ impl web_transport_trait::Session for Session {
     }
 
     fn send_datagram(&self, data: Bytes) -> Result<(), Self::Error> {
-        Self::send_datagram(self, data)
+        Ok(Self::send_datagram(self, data)?)
     }
 
     async fn recv_datagram(&self) -> Result<Bytes, Self::Error> {
@@ -574,3 +1311,311 @@ impl web_transport_trait::Session for Session {
         Self::max_datagram_size(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing;
+
+    // Open a raw QUIC stream on the client and write the given stream header.
+    async fn open_raw_uni(client: &Session, header: &[u8]) -> quinn::SendStream {
+        let mut send = client.conn.open_uni().await.unwrap();
+        send.write_all(header).await.unwrap();
+        send
+    }
+
+    fn header_uni(session_id: VarInt) -> Vec<u8> {
+        let mut header = Vec::new();
+        StreamUni::WEBTRANSPORT.encode(&mut header);
+        session_id.encode(&mut header);
+        header
+    }
+
+    async fn read_all(mut recv: RecvStream) -> Vec<u8> {
+        recv.read_to_end(1024).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_accept() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        let mut send = client.open_uni().await.unwrap();
+        send.write_all(b"uni").await.unwrap();
+        send.finish().unwrap();
+
+        let (mut send, recv) = client.open_bi().await.unwrap();
+        send.write_all(b"bi").await.unwrap();
+        send.finish().unwrap();
+
+        assert_eq!(read_all(server.accept_uni().await.unwrap()).await, b"uni");
+
+        let (mut reply, recv2) = server.accept_bi().await.unwrap();
+        assert_eq!(read_all(recv2).await, b"bi");
+        reply.write_all(b"ok").await.unwrap();
+        reply.finish().unwrap();
+        assert_eq!(read_all(recv).await, b"ok");
+
+        // Only the bidirectional stream is still open.
+        let stats = server.stats();
+        assert_eq!((stats.streams_uni, stats.streams_bi), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_accept_many() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        for i in 0..50u8 {
+            let mut send = client.open_uni().await.unwrap();
+            send.write_all(&[i]).await.unwrap();
+            send.finish().unwrap();
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..50 {
+            received.extend(read_all(server.accept_uni().await.unwrap()).await);
+        }
+
+        received.sort();
+        assert_eq!(received, (0..50).collect::<Vec<u8>>());
+    }
+
+    #[tokio::test]
+    async fn test_accept_split_header() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        // Write the header one byte at a time, which decodes across several polls.
+        let accept = tokio::spawn({
+            let server = server.clone();
+            async move { server.accept_uni().await }
+        });
+
+        let mut send = client.conn.open_uni().await.unwrap();
+        for byte in header_uni(client.session_id.unwrap()) {
+            send.write_all(&[byte]).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        send.write_all(b"data").await.unwrap();
+        send.finish().unwrap();
+
+        let recv = accept.await.unwrap().unwrap();
+        assert_eq!(read_all(recv).await, b"data");
+    }
+
+    #[tokio::test]
+    async fn test_accept_unknown_session() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        // Streams are only accepted while the application is waiting for one.
+        let accept = tokio::spawn({
+            let server = server.clone();
+            async move { server.accept_uni().await }
+        });
+
+        // A stream for another session is rejected without failing accept.
+        let bad = open_raw_uni(&client, &header_uni(VarInt::from_u32(1024))).await;
+        let code = bad.stopped().await.unwrap();
+        assert_eq!(
+            code,
+            Some(quinn::VarInt::from_u64(web_transport_proto::H3_ID_ERROR).unwrap())
+        );
+
+        let mut good = client.open_uni().await.unwrap();
+        good.write_all(b"good").await.unwrap();
+        good.finish().unwrap();
+
+        assert_eq!(read_all(accept.await.unwrap().unwrap()).await, b"good");
+        assert_eq!(server.stats().streams_rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn test_accept_header_timeout() {
+        let limits = Limits {
+            stream_header_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+
+        let (client, server) = testing::session(limits).await;
+
+        let accept = tokio::spawn({
+            let server = server.clone();
+            async move { server.accept_uni().await }
+        });
+
+        // Only send the first byte of a two byte stream type.
+        let slow = open_raw_uni(&client, &[0x40]).await;
+
+        // A stream opened afterwards isn't blocked by the slow one.
+        let mut good = client.open_uni().await.unwrap();
+        good.write_all(b"good").await.unwrap();
+        good.finish().unwrap();
+
+        let recv = accept.await.unwrap().unwrap();
+        assert_eq!(read_all(recv).await, b"good");
+
+        // Keep accepting, since pending headers are only polled while the application is waiting.
+        let _accept = tokio::spawn(async move { server.accept_uni().await });
+
+        let code = slow.stopped().await.unwrap();
+        assert_eq!(
+            code,
+            Some(quinn::VarInt::from_u64(web_transport_proto::H3_REQUEST_INCOMPLETE).unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_accept_buffered() {
+        let (client, server) = testing::connect(|_| {}).await;
+
+        // Send SETTINGS and the CONNECT request by hand so we can open streams before the session is accepted.
+        let mut settings = web_transport_proto::Settings::default();
+        settings.enable_webtransport(1);
+        let mut buf = Vec::new();
+        settings.encode(&mut buf);
+        let mut control = client.open_uni().await.unwrap();
+        control.write_all(&buf).await.unwrap();
+
+        let (mut connect, _connect_recv) = client.open_bi().await.unwrap();
+        let mut buf = Vec::new();
+        web_transport_proto::ConnectRequest {
+            url: url::Url::parse("https://localhost/").unwrap(),
+        }
+        .encode(&mut buf);
+        connect.write_all(&buf).await.unwrap();
+
+        // The first stream never finishes its header, which must not block the second.
+        let session_id = VarInt::from_u64(connect.id().index() * 4).unwrap();
+        let mut slow = client.open_uni().await.unwrap();
+        slow.write_all(&header_uni(session_id)[..2]).await.unwrap();
+
+        let mut good = client.open_uni().await.unwrap();
+        good.write_all(&header_uni(session_id)).await.unwrap();
+        good.write_all(b"good").await.unwrap();
+        good.finish().unwrap();
+
+        let request = crate::Request::accept(server).await.unwrap();
+        let server = request.ok().await.unwrap();
+
+        let recv = tokio::time::timeout(Duration::from_secs(1), server.accept_uni())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(read_all(recv).await, b"good");
+    }
+
+    #[tokio::test]
+    async fn test_reject_buffered() {
+        let (client, server) = testing::connect(|_| {}).await;
+
+        // Returns the code the server used to reject a stream that started with `header`.
+        async fn reject(
+            client: &quinn::Connection,
+            server: &quinn::Connection,
+            header: &[u8],
+        ) -> u64 {
+            let mut send = client.open_uni().await.unwrap();
+            send.write_all(header).await.unwrap();
+
+            let recv = server.accept_uni().await.unwrap();
+            let mut stream = PendingUni::new(recv, Instant::now() + Duration::from_secs(1));
+            poll_fn(|cx| stream.poll_type(cx, &Qlog::default()))
+                .await
+                .unwrap();
+
+            SessionAccept::reject_buffered_uni(stream);
+            send.stopped().await.unwrap().unwrap().into_inner()
+        }
+
+        // Only WebTransport streams are rejected with WT_BUFFERED_STREAM_REJECTED.
+        let code = reject(&client, &server, &header_uni(VarInt::from_u32(0))).await;
+        assert_eq!(code, web_transport_proto::WT_BUFFERED_STREAM_REJECTED);
+
+        // A grease stream type.
+        let mut header = Vec::new();
+        VarInt::from_u32(0x21).encode(&mut header);
+        let code = reject(&client, &server, &header).await;
+        assert_eq!(code, web_transport_proto::H3_STREAM_CREATION_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_accept_concurrent() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        // Both tasks wait on the same accept state, so each needs to be woken when a stream arrives.
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let server = server.clone();
+                tokio::spawn(async move { read_all(server.accept_uni().await.unwrap()).await })
+            })
+            .collect();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        for _ in 0..2 {
+            let mut send = client.open_uni().await.unwrap();
+            send.write_all(b"hello").await.unwrap();
+            send.finish().unwrap();
+        }
+
+        for task in tasks {
+            let data = tokio::time::timeout(Duration::from_secs(1), task).await;
+            assert_eq!(data.unwrap().unwrap(), b"hello");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_data_on_arrival() {
+        let limits = Limits {
+            initial_max_data: 1024,
+            ..Default::default()
+        };
+
+        // Limit how much the client can buffer, so writes block until the data is received.
+        let transport = |config: &mut quinn::TransportConfig| {
+            config.send_window(64 * 1024);
+        };
+        let (client, server) = testing::session_with(limits, transport, None).await;
+
+        // Bypass our own send credit, so only the server's limit applies.
+        let mut send = open_raw_uni(&client, &header_uni(client.session_id.unwrap())).await;
+        let write = send.write_all(&[0u8; 256 * 1024]);
+        let res = tokio::time::timeout(Duration::from_millis(200), write).await;
+        assert!(res.is_err(), "the peer exceeded the session data limit");
+
+        // Only the QUIC receive window arrived, which approximates the session limit.
+        let stats = server.conn.stats();
+        assert!(stats.udp_rx.bytes < 64 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_datagram_stats() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        client.send_datagram(Bytes::from_static(b"hello")).unwrap();
+        assert_eq!(server.read_datagram().await.unwrap(), "hello");
+
+        // A datagram that's too large fails locally and is counted separately.
+        let large = Bytes::from(vec![0u8; client.max_datagram_size() + 1]);
+        let err = client.send_datagram(large).unwrap_err();
+        assert!(matches!(err, SendDatagramError::TooLarge(_)));
+
+        let stats = client.stats();
+        assert_eq!((stats.datagrams_sent, stats.datagrams_failed), (1, 1));
+        assert_eq!(server.stats().datagrams_received, 1);
+    }
+
+    #[tokio::test]
+    async fn test_accept_closed() {
+        let (client, server) = testing::session(Limits::default()).await;
+
+        client.close(42, b"bye");
+
+        let err = server.accept_uni().await.unwrap_err();
+        assert!(matches!(err, SessionError::ConnectionError(_)));
+
+        // Every call after the connection is closed returns an error too.
+        assert!(server.accept_bi().await.is_err());
+        assert!(server.accept_uni().await.is_err());
+    }
+}
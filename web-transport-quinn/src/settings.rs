@@ -1,8 +1,13 @@
 use futures::try_join;
-use std::io;
+use std::{future::poll_fn, io};
+
+use bytes::Bytes;
+use web_transport_proto::{Setting, StreamUni, VarInt};
 
 use thiserror::Error;
 
+use crate::{Limits, Owner, PendingSet, PendingUni, Qlog, SessionError};
+
 #[derive(Error, Debug, Clone)]
 pub enum SettingsError {
     #[error("quic stream was closed early")]
@@ -14,6 +19,12 @@ pub enum SettingsError {
     #[error("WebTransport is not supported")]
     WebTransportUnsupported,
 
+    #[error("SETTINGS frame is too large")]
+    TooLarge,
+
+    #[error("duplicate critical stream")]
+    DuplicateStream,
+
     #[error("connection error")]
     ConnectionError(#[from] quinn::ConnectionError),
 
@@ -25,27 +36,149 @@ pub enum SettingsError {
 }
 
 pub struct Settings {
-    // A reference to the send/recv stream, so we don't close it until dropped.
+    // A reference to the send stream, so we don't close it until dropped.
     #[allow(dead_code)]
     send: quinn::SendStream,
 
-    #[allow(dead_code)]
-    recv: quinn::RecvStream,
+    // The peer's control stream and any data after SETTINGS, taken by the session.
+    control: Option<(quinn::RecvStream, Bytes)>,
+
+    // The peer's QPACK streams, taken by the session.
+    qpack: QpackStreams,
+
+    // Any other streams received during the handshake, taken by the session.
+    streams: Vec<PendingUni>,
+
+    // The SETTINGS sent by the peer.
+    remote: web_transport_proto::Settings,
 }
 
 impl Settings {
     // Establish the H3 connection.
-    pub async fn connect(conn: &quinn::Connection) -> Result<Self, SettingsError> {
-        let recv = Self::accept(conn);
-        let send = Self::open(conn);
+    // Fails if the peer doesn't support WebTransport, unless `http` is set because we also serve plain HTTP/3.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "settings", skip_all, err)
+    )]
+    pub async fn connect(
+        conn: &quinn::Connection,
+        qlog: &Qlog,
+        limits: &Limits,
+        http: bool,
+    ) -> Result<Self, SettingsError> {
+        let recv = Self::accept(conn, qlog, limits, http);
+        let send = Self::open(conn, qlog, limits);
 
         // Run both tasks concurrently until one errors or they both complete.
-        let (send, recv) = try_join!(send, recv)?;
-        Ok(Self { send, recv })
+        let (send, (recv, remaining, qpack, streams, remote)) = try_join!(send, recv)?;
+        Ok(Self {
+            send,
+            control: Some((recv, remaining)),
+            qpack,
+            streams,
+            remote,
+        })
+    }
+
+    // Take the peer's control stream and any data after SETTINGS.
+    pub fn take_control(&mut self) -> Option<(quinn::RecvStream, Bytes)> {
+        self.control.take()
+    }
+
+    // The SETTINGS sent by the peer.
+    pub fn remote(&self) -> &web_transport_proto::Settings {
+        &self.remote
+    }
+
+    // Take the QPACK streams that arrived during the handshake.
+    pub fn take_qpack(&mut self) -> QpackStreams {
+        std::mem::take(&mut self.qpack)
     }
 
-    async fn accept(conn: &quinn::Connection) -> Result<quinn::RecvStream, SettingsError> {
-        let mut recv = conn.accept_uni().await?;
+    // Take the streams that arrived during the handshake.
+    pub fn take_streams(&mut self) -> Vec<PendingUni> {
+        std::mem::take(&mut self.streams)
+    }
+
+    // Accept unidirectional streams until we find the control stream, dispatching any others based on their type.
+    #[allow(clippy::type_complexity)]
+    async fn accept(
+        conn: &quinn::Connection,
+        qlog: &Qlog,
+        limits: &Limits,
+        http: bool,
+    ) -> Result<
+        (
+            quinn::RecvStream,
+            Bytes,
+            QpackStreams,
+            Vec<PendingUni>,
+            web_transport_proto::Settings,
+        ),
+        SettingsError,
+    > {
+        // Read the stream types in parallel, since the control stream might not be first.
+        let mut pending = PendingSet::<PendingUni>::new();
+        let mut qpack = QpackStreams::default();
+        let mut streams = Vec::<PendingUni>::new();
+        let mut buffered = 0;
+
+        let mut recv = loop {
+            tokio::select! {
+                res = conn.accept_uni() => {
+                    let mut recv = res?;
+
+                    // Don't let the peer make us hold an unbounded number of streams.
+                    if pending.len() + streams.len() >= limits.max_pending_streams {
+                        log::debug!("too many pending unidirectional streams: id={}", recv.id());
+                        Self::reject(&mut recv, web_transport_proto::H3_EXCESSIVE_LOAD);
+                        continue;
+                    }
+
+                    let deadline = tokio::time::Instant::now() + limits.stream_header_timeout;
+                    pending.insert(PendingUni::new(recv, deadline));
+                }
+                (stream, typ) = poll_fn(|cx| pending.poll_next(cx, |stream, cx| stream.poll_type(cx, qlog))) => {
+                    let typ = match typ {
+                        Ok(typ) => typ,
+                        Err(SessionError::ConnectionError(err)) => return Err(err.into()),
+                        Err(err) => {
+                            let mut recv = stream.into_stream();
+                            log::debug!("rejecting unidirectional stream: id={} err={err}", recv.id());
+                            Self::reject(&mut recv, web_transport_proto::H3_REQUEST_INCOMPLETE);
+                            continue;
+                        }
+                    };
+
+                    match StreamUni(typ) {
+                        StreamUni::CONTROL => break stream.into_stream(),
+                        StreamUni::QPACK_ENCODER | StreamUni::QPACK_DECODER => {
+                            if !qpack.insert(StreamUni(typ), stream.into_stream()) {
+                                QpackStreams::duplicate(conn);
+                                return Err(SettingsError::DuplicateStream);
+                            }
+                        }
+                        // WebTransport streams may arrive first, so they're buffered until the session is established.
+                        StreamUni::WEBTRANSPORT if buffered >= limits.max_buffered_streams => {
+                            log::debug!("too many buffered unidirectional streams");
+                            Self::reject(&mut stream.into_stream(), web_transport_proto::WT_BUFFERED_STREAM_REJECTED);
+                        }
+                        StreamUni::WEBTRANSPORT => {
+                            log::debug!("buffering unidirectional stream: id={}", stream.id());
+                            buffered += 1;
+                            streams.push(stream);
+                        }
+                        // Let the session read the rest of the header.
+                        _ => streams.push(stream),
+                    }
+                }
+            }
+        };
+
+        // Hand off any streams we haven't finished reading the type for, in the order they were opened.
+        streams.extend(pending.into_streams());
+        streams.sort_by_key(PendingUni::id);
+
         let mut buf = Vec::new();
 
         loop {
@@ -57,26 +190,77 @@ impl Settings {
             // Look at the buffer we've already read.
             let mut limit = io::Cursor::new(&buf);
 
-            let settings = match web_transport_proto::Settings::decode(&mut limit) {
+            let settings = match web_transport_proto::Settings::decode_frame(&mut limit) {
                 Ok(settings) => settings,
-                Err(web_transport_proto::SettingsError::UnexpectedEnd) => continue, // More data needed.
+                Err(web_transport_proto::SettingsError::UnexpectedEnd) => {
+                    // Don't let the peer make us buffer forever.
+                    if buf.len() > limits.max_settings_size {
+                        let code = web_transport_proto::H3_EXCESSIVE_LOAD.try_into().unwrap();
+                        conn.close(code, b"SETTINGS too large");
+                        return Err(SettingsError::TooLarge);
+                    }
+
+                    continue; // More data needed.
+                }
+                // The first frame on the control stream must be SETTINGS.
+                Err(e @ web_transport_proto::SettingsError::UnexpectedFrame(_)) => {
+                    let code = web_transport_proto::H3_MISSING_SETTINGS.try_into().unwrap();
+                    conn.close(code, b"expected SETTINGS");
+                    return Err(e.into());
+                }
                 Err(e) => return Err(e.into()),
             };
 
             log::debug!("received SETTINGS frame: {settings:?}");
+            qlog.settings(Owner::Remote, recv.id(), &settings);
 
-            if settings.supports_webtransport() == 0 {
+            if settings.supports_webtransport() == 0 && !http {
                 return Err(SettingsError::WebTransportUnsupported);
             }
 
-            return Ok(recv);
+            // Anything after SETTINGS is read by the session.
+            let position = limit.position() as usize;
+            let remaining = Bytes::from(buf).slice(position..);
+
+            return Ok((recv, remaining, qpack, streams, settings));
         }
     }
 
-    async fn open(conn: &quinn::Connection) -> Result<quinn::SendStream, SettingsError> {
+    async fn open(
+        conn: &quinn::Connection,
+        qlog: &Qlog,
+        limits: &Limits,
+    ) -> Result<quinn::SendStream, SettingsError> {
         let mut settings = web_transport_proto::Settings::default();
         settings.enable_webtransport(1);
 
+        // Let the peer know how large of a CONNECT request/response we'll accept.
+        if let Ok(size) = VarInt::from_u64(limits.max_field_section_size) {
+            settings.insert(Setting::MAX_FIELD_SECTION_SIZE, size);
+        }
+
+        // Advertise the initial session flow control limits.
+        let flow = [
+            (
+                Setting::WEBTRANSPORT_INITIAL_MAX_DATA,
+                limits.initial_max_data,
+            ),
+            (
+                Setting::WEBTRANSPORT_INITIAL_MAX_STREAMS_UNI,
+                limits.initial_max_streams_uni,
+            ),
+            (
+                Setting::WEBTRANSPORT_INITIAL_MAX_STREAMS_BIDI,
+                limits.initial_max_streams_bi,
+            ),
+        ];
+
+        for (setting, value) in flow {
+            if let Ok(value) = VarInt::from_u64(value) {
+                settings.insert(setting, value);
+            }
+        }
+
         log::debug!("sending SETTINGS frame: {settings:?}");
 
         let mut buf = Vec::new();
@@ -85,6 +269,89 @@ impl Settings {
         let mut send = conn.open_uni().await?;
         send.write_all(&buf).await?;
 
+        qlog.stream_type(Owner::Local, send.id(), StreamUni::CONTROL.0);
+        qlog.settings(Owner::Local, send.id(), &settings);
+
+        log::debug!("sent SETTINGS frame: {settings:?}");
+
         Ok(send)
     }
+
+    // Send STOP_SENDING with an HTTP/3 error code.
+    fn reject(recv: &mut quinn::RecvStream, code: u64) {
+        // Ignore the error if the stream is already closed.
+        recv.stop(code.try_into().unwrap()).ok();
+    }
+}
+
+// The peer's QPACK streams, which we keep open but otherwise ignore since we don't use the dynamic table.
+// RFC 9114 Section 6.2.1: only one stream of each type is allowed per connection.
+#[derive(Default)]
+pub(crate) struct QpackStreams {
+    encoder: Option<quinn::RecvStream>,
+    decoder: Option<quinn::RecvStream>,
+}
+
+impl QpackStreams {
+    // Keep the stream open, returning false if the peer already opened one of the same type.
+    pub fn insert(&mut self, typ: StreamUni, recv: quinn::RecvStream) -> bool {
+        let slot = match typ {
+            StreamUni::QPACK_ENCODER => &mut self.encoder,
+            StreamUni::QPACK_DECODER => &mut self.decoder,
+            _ => unreachable!("not a QPACK stream"),
+        };
+
+        if slot.is_some() {
+            log::warn!("duplicate QPACK stream: id={} type={typ:?}", recv.id());
+            return false;
+        }
+
+        *slot = Some(recv);
+        true
+    }
+
+    // Close the connection because the peer opened a second stream of the same type.
+    pub fn duplicate(conn: &quinn::Connection) {
+        let code = web_transport_proto::H3_STREAM_CREATION_ERROR;
+        conn.close(code.try_into().unwrap(), b"duplicate QPACK stream");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web_transport_proto::Frame;
+
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn test_missing_settings() {
+        let (client, server) = testing::connect(|_| {}).await;
+
+        // A control stream that starts with GOAWAY instead of SETTINGS.
+        let mut buf = Vec::new();
+        StreamUni::CONTROL.encode(&mut buf);
+        Frame::GOAWAY.encode(&mut buf);
+        buf.extend_from_slice(&[1, 0]);
+
+        let mut control = client.open_uni().await.unwrap();
+        control.write_all(&buf).await.unwrap();
+
+        let res = Settings::connect(&server, &Qlog::default(), &Limits::default(), false).await;
+        assert!(matches!(
+            res,
+            Err(SettingsError::ProtoError(
+                web_transport_proto::SettingsError::UnexpectedFrame(Frame::GOAWAY)
+            ))
+        ));
+
+        // The connection is closed too.
+        let quinn::ConnectionError::ApplicationClosed(close) = client.closed().await else {
+            panic!("expected the server to close the connection");
+        };
+        assert_eq!(
+            close.error_code.into_inner(),
+            web_transport_proto::H3_MISSING_SETTINGS
+        );
+    }
 }
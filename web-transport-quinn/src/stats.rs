@@ -0,0 +1,195 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// A snapshot of statistics for a WebTransport session. See [`crate::Session::stats`].
+///
+/// The network statistics are measured by QUIC for the entire connection, including any HTTP/3 overhead.
+/// The datagram and stream statistics only count WebTransport datagrams and streams.
+#[derive(Debug, Default, Clone, Copy)]
+#[non_exhaustive]
+pub struct SessionStats {
+    /// The smoothed round-trip time.
+    pub rtt: Duration,
+
+    /// The minimum round-trip time observed, ignoring ACK delay.
+    ///
+    /// The difference between [`Self::rtt`] and this value is a cheap estimate of queuing delay.
+    /// Quinn does not expose the RTT variance itself.
+    pub min_rtt: Duration,
+
+    /// The congestion window in bytes.
+    pub congestion_window: u64,
+
+    /// The number of times the congestion controller reduced the window.
+    pub congestion_events: u64,
+
+    /// The number of UDP payload bytes sent.
+    pub bytes_sent: u64,
+
+    /// The number of UDP payload bytes received.
+    pub bytes_received: u64,
+
+    /// The number of bytes declared lost.
+    pub bytes_lost: u64,
+
+    /// The number of QUIC packets sent.
+    pub packets_sent: u64,
+
+    /// The number of QUIC packets declared lost.
+    pub packets_lost: u64,
+
+    /// The number of datagrams queued for sending.
+    pub datagrams_sent: u64,
+
+    /// The number of datagrams received.
+    pub datagrams_received: u64,
+
+    /// The number of datagrams that were dropped locally before being sent, because the send buffer was full.
+    ///
+    /// Which datagram is dropped depends on the [`crate::DatagramDropPolicy`].
+    ///
+    /// This is a lower bound with [`crate::DatagramDropPolicy::DropOldest`] and QUIC datagrams:
+    /// Quinn may evict several queued datagrams to make room for a large one, but doesn't say how many, so only one is counted.
+    pub datagrams_dropped: u64,
+
+    /// The number of datagrams that failed to send locally, where [`crate::Session::send_datagram`] or a variant returned an error.
+    ///
+    /// This counts every [`crate::SendDatagramError`], such as a datagram that's too large or a peer that doesn't support datagrams.
+    /// It doesn't include datagrams that the peer received and discarded, which can't be detected.
+    pub datagrams_failed: u64,
+
+    /// The number of DATAGRAM capsules received but dropped because the application wasn't reading fast enough.
+    ///
+    /// Quinn doesn't report QUIC datagrams dropped from its receive buffer, so those aren't included.
+    pub datagrams_dropped_received: u64,
+
+    /// The number of unidirectional streams currently open.
+    pub streams_uni: u64,
+
+    /// The number of bidirectional streams currently open.
+    pub streams_bi: u64,
+
+    /// The number of incoming streams rejected before they were accepted.
+    ///
+    /// This includes streams with an invalid header, a header that took too long, or that exceeded [`crate::Limits::max_pending_streams`].
+    pub streams_rejected: u64,
+}
+
+impl SessionStats {
+    pub(crate) fn new(quic: quinn::ConnectionStats, counters: &SessionCounters) -> Self {
+        Self {
+            rtt: quic.path.rtt,
+            min_rtt: quic.path.min_rtt,
+            congestion_window: quic.path.cwnd,
+            congestion_events: quic.path.congestion_events,
+            bytes_sent: quic.udp_tx.bytes,
+            bytes_received: quic.udp_rx.bytes,
+            bytes_lost: quic.path.lost_bytes,
+            packets_sent: quic.path.sent_packets,
+            packets_lost: quic.path.lost_packets,
+            datagrams_sent: counters.datagrams_sent.load(Ordering::Relaxed),
+            datagrams_received: counters.datagrams_received.load(Ordering::Relaxed),
+            datagrams_dropped: counters.datagrams_dropped.load(Ordering::Relaxed),
+            datagrams_failed: counters.datagrams_failed.load(Ordering::Relaxed),
+            datagrams_dropped_received: counters.datagrams_dropped_received.load(Ordering::Relaxed),
+            streams_uni: counters.streams_uni.load(Ordering::Relaxed),
+            streams_bi: counters.streams_bi.load(Ordering::Relaxed),
+            streams_rejected: counters.streams_rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// A rough estimate of the available bandwidth in bytes per second, computed as the congestion window over the RTT.
+    ///
+    /// Returns None until an RTT sample is available.
+    pub fn estimated_bandwidth(&self) -> Option<u64> {
+        let rtt = self.rtt.as_nanos();
+        if rtt == 0 {
+            return None;
+        }
+
+        let bandwidth = self.congestion_window as u128 * 1_000_000_000 / rtt;
+        Some(bandwidth.try_into().unwrap_or(u64::MAX))
+    }
+}
+
+// Counters for everything that QUIC can't measure for us, shared between Session clones.
+#[derive(Default, Debug)]
+pub(crate) struct SessionCounters {
+    datagrams_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    datagrams_dropped: AtomicU64,
+    datagrams_failed: AtomicU64,
+    datagrams_dropped_received: AtomicU64,
+    streams_uni: AtomicU64,
+    streams_bi: AtomicU64,
+    streams_rejected: AtomicU64,
+}
+
+impl SessionCounters {
+    pub fn datagram_sent(&self) {
+        self.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn datagram_received(&self) {
+        self.datagrams_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn datagram_dropped(&self) {
+        self.datagrams_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn datagram_failed(&self) {
+        self.datagrams_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn datagram_dropped_received(&self) {
+        self.datagrams_dropped_received
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stream_rejected(&self) {
+        self.streams_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Returns a handle that counts the stream as open until it's dropped.
+    pub fn open_uni(self: &Arc<Self>) -> Arc<OpenStream> {
+        self.streams_uni.fetch_add(1, Ordering::Relaxed);
+        Arc::new(OpenStream {
+            counters: self.clone(),
+            bi: false,
+        })
+    }
+
+    // Returns a handle that counts the stream as open until it's dropped.
+    // The handle is shared by both halves, so the stream is open until both are dropped.
+    pub fn open_bi(self: &Arc<Self>) -> Arc<OpenStream> {
+        self.streams_bi.fetch_add(1, Ordering::Relaxed);
+        Arc::new(OpenStream {
+            counters: self.clone(),
+            bi: true,
+        })
+    }
+}
+
+// Decrements the number of open streams on drop.
+#[derive(Debug)]
+pub(crate) struct OpenStream {
+    counters: Arc<SessionCounters>,
+    bi: bool,
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        let count = match self.bi {
+            true => &self.counters.streams_bi,
+            false => &self.counters.streams_uni,
+        };
+
+        count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
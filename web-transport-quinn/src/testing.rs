@@ -0,0 +1,91 @@
+// Helpers for tests that need a real QUIC connection over localhost.
+use std::sync::Arc;
+
+use quinn::rustls;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use url::Url;
+
+use crate::{crypto, Handshake, HttpHandler, Incoming, Limits, Qlog, Request, Session, ALPN};
+
+// Both sides of a QUIC connection that negotiated the HTTP/3 ALPN, without any HTTP/3 handshake.
+pub(crate) async fn connect(
+    transport: impl Fn(&mut quinn::TransportConfig),
+) -> (quinn::Connection, quinn::Connection) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let chain = vec![CertificateDer::from(cert.cert.der().to_vec())];
+    let key = PrivateKeyDer::from(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+
+    let provider = crypto::default_provider();
+
+    let mut config = quinn::TransportConfig::default();
+    transport(&mut config);
+    let config = Arc::new(config);
+
+    let mut server = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(chain.clone(), key)
+        .unwrap();
+    server.alpn_protocols = vec![ALPN.as_bytes().to_vec()];
+
+    let server: quinn::crypto::rustls::QuicServerConfig = server.try_into().unwrap();
+    let mut server = quinn::ServerConfig::with_crypto(Arc::new(server));
+    server.transport_config(config.clone());
+
+    let server = quinn::Endpoint::server(server, "127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(chain[0].clone()).unwrap();
+
+    let mut client = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client.alpn_protocols = vec![ALPN.as_bytes().to_vec()];
+
+    let client: quinn::crypto::rustls::QuicClientConfig = client.try_into().unwrap();
+    let mut client = quinn::ClientConfig::new(Arc::new(client));
+    client.transport_config(config);
+
+    let endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    let connecting = endpoint.connect_with(client, addr, "localhost").unwrap();
+
+    let (client, server) = tokio::join!(connecting, async { server.accept().await.unwrap().await });
+
+    (client.unwrap(), server.unwrap())
+}
+
+// A WebTransport session established over localhost, returning the client and server sides.
+pub(crate) async fn session(limits: Limits) -> (Session, Session) {
+    session_with(limits, |_| {}, None).await
+}
+
+pub(crate) async fn session_with(
+    limits: Limits,
+    transport: impl Fn(&mut quinn::TransportConfig),
+    http: Option<Arc<dyn HttpHandler>>,
+) -> (Session, Session) {
+    // Match the transport config used by the client and server builders.
+    let (client, server) = connect(|config| {
+        config.receive_window(limits.receive_window());
+        transport(config)
+    })
+    .await;
+    let url = Url::parse("https://localhost/").unwrap();
+
+    let (client, server) = tokio::join!(
+        Session::connect_with(client, url, Qlog::default(), limits.clone()),
+        async {
+            let handshake = Request::accept_with(server, Qlog::default(), limits, http).await?;
+            let Handshake::Incoming(Incoming::WebTransport(request)) = handshake else {
+                panic!("expected a CONNECT request");
+            };
+            Ok::<_, crate::ServerError>(request.ok().await.unwrap())
+        }
+    );
+
+    (client.unwrap(), server.unwrap())
+}
@@ -130,6 +130,12 @@ pub trait SendStream: Send {
     /// Send an immediate reset code, closing the stream.
     fn reset(&mut self, code: u32);
 
+    /// Reset the stream with the given code if it's not delivered by the deadline.
+    ///
+    /// This is useful for data that becomes worthless after some time, freeing bandwidth for fresher streams.
+    /// What counts as delivered depends on the implementation, ex. acknowledged by the peer or written to the socket.
+    fn set_deadline(&mut self, deadline: std::time::Instant, code: u32);
+
     /// Mark the stream as finished and wait for all data to be acknowledged.
     fn finish(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
 
@@ -193,6 +199,18 @@ pub trait RecvStream: Send {
         }
     }
 
+    /// Read the next chunk of data in any order, up to the max size.
+    ///
+    /// Each chunk includes its offset in the stream, so gaps and duplicates can be detected.
+    /// This is useful when late data is useless, as a lost packet won't block later data.
+    /// Implementations without unordered delivery return the data in order instead.
+    ///
+    /// Don't mix this with ordered reads on the same stream; Quinn returns an error if you do.
+    fn read_chunk_unordered(
+        &mut self,
+        max: usize,
+    ) -> impl Future<Output = Result<Option<Chunk>, Self::Error>> + Send;
+
     /// Send a `STOP_SENDING` QUIC code.
     fn stop(&mut self, code: u32);
 
@@ -227,3 +245,13 @@ pub trait RecvStream: Send {
         }
     }
 }
+
+/// A chunk of data read from a stream, along with its position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The offset of the data in the stream.
+    pub offset: u64,
+
+    /// The data itself.
+    pub bytes: Bytes,
+}
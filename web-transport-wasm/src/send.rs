@@ -1,10 +1,17 @@
 use bytes::Buf;
 use js_sys::{Reflect, Uint8Array};
+use wasm_bindgen::JsValue;
 use web_sys::WebTransportSendStream;
 
 use crate::Error;
 use web_streams::Writer;
 
+/// A group of streams that share bandwidth fairly with other groups, created by [`crate::Session::send_group`].
+///
+/// This wraps the browser's `WebTransportSendGroup`.
+#[derive(Clone, Debug)]
+pub struct SendGroup(pub(crate) JsValue);
+
 /// A stream of bytes sent to the remote peer.
 pub struct SendStream {
     stream: WebTransportSendStream,
@@ -55,6 +62,14 @@ impl SendStream {
             .expect("failed to set priority");
     }
 
+    /// Move the stream into a [SendGroup], or out of one with `None`.
+    ///
+    /// The priority now orders the stream relative to other streams in the group.
+    pub fn set_send_group(&mut self, group: Option<&SendGroup>) {
+        let group = group.map(|group| group.0.clone()).unwrap_or(JsValue::NULL);
+        Reflect::set(&self.stream, &"sendGroup".into(), &group).expect("failed to set send group");
+    }
+
     /// Block until the stream has been closed and return the error code, if any.
     pub async fn closed(&self) -> Result<Option<u8>, Error> {
         let err = match self.writer.closed().await {
@@ -1,12 +1,13 @@
 use bytes::Bytes;
-use js_sys::Uint8Array;
+use js_sys::{Function, Reflect, Uint8Array};
 use url::Url;
+use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
     WebTransport, WebTransportBidirectionalStream, WebTransportCloseInfo, WebTransportSendStream,
 };
 
-use crate::{Error, RecvStream, SendStream};
+use crate::{Error, RecvStream, SendGroup, SendStream};
 use web_streams::{Reader, Writer};
 
 /// A session represents a connection between a client and a server.
@@ -76,6 +77,15 @@ impl Session {
         Ok(send)
     }
 
+    /// Create a new [SendGroup], which streams can join with [`SendStream::set_send_group`].
+    ///
+    /// Each group gets a fair share of bandwidth, while streams within a group are sent in priority order.
+    pub fn send_group(&self) -> Result<SendGroup, Error> {
+        let create: Function = Reflect::get(&self.inner, &"createSendGroup".into())?.dyn_into()?;
+        let group = create.call0(&self.inner)?;
+        Ok(SendGroup(group))
+    }
+
     /// Send a datagram over the network.
     pub async fn send_datagram(&mut self, payload: Bytes) -> Result<(), Error> {
         let mut writer = Writer::new(&self.inner.datagrams().writable())?;
@@ -29,6 +29,9 @@ pub enum Error {
     #[error("stream stop: {0}")]
     StreamStop(VarInt),
 
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+
     #[error("short frame")]
     Short,
 
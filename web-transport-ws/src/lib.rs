@@ -1,10 +1,12 @@
 mod error;
 mod frame;
+mod outbound;
 mod session;
 mod stream;
 
 pub(crate) use error::*;
 pub(crate) use frame::*;
+pub(crate) use outbound::*;
 pub(crate) use stream::*;
 
 pub use session::*;
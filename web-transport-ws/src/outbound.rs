@@ -0,0 +1,356 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::Notify;
+
+use crate::{Error, Frame, StreamId};
+
+// Outgoing STREAM frames, scheduled by send group and priority.
+//
+// Each stream may have a single frame queued, so writes block until the previous frame is sent.
+// Groups take turns sending a frame, while streams within a group are sent in priority order.
+#[derive(Clone, Default)]
+pub(crate) struct Outbound {
+    state: Arc<Mutex<OutboundState>>,
+
+    // Notified whenever a frame is queued or the queue is closed, waking the single consumer.
+    ready: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct OutboundState {
+    pending: HashMap<StreamId, Pending>,
+
+    // The queued streams in each group, in the order they should be sent.
+    groups: BTreeMap<u64, BTreeMap<Rank, StreamId>>,
+
+    // Streams blocked until their queued frame is sent or dropped, woken individually.
+    // Each stream has a single writer, so a stored permit is never consumed by the wrong one.
+    writers: HashMap<StreamId, Arc<Notify>>,
+
+    // Incremented for each frame, so frames with the same priority are sent in order.
+    sequence: u64,
+
+    // The group that sent the last frame.
+    last_group: u64,
+
+    // Streams with a deadline that haven't written their FIN yet.
+    deadlines: HashMap<StreamId, Deadline>,
+
+    closed: bool,
+}
+
+// The highest priority first, then the oldest frame.
+type Rank = (Reverse<i32>, u64);
+
+struct Pending {
+    frame: Frame,
+    group: u64,
+    rank: Rank,
+}
+
+#[derive(Default)]
+struct Deadline {
+    // The stream was reset, so any future writes should fail.
+    expired: bool,
+
+    // The SendStream was dropped, so nobody is left to check if it expired.
+    dropped: bool,
+}
+
+// The send group and priority of a stream. Group 0 contains streams without a group.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Schedule {
+    pub group: u64,
+    pub order: i32,
+}
+
+impl Outbound {
+    // Queue a frame, waiting until the stream's previous frame has been sent.
+    pub async fn send(&self, id: StreamId, frame: Frame, schedule: Schedule) -> Result<(), Error> {
+        loop {
+            let notify = {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return Err(Error::Closed);
+                }
+
+                if state.deadlines.get(&id).is_some_and(|d| d.expired) {
+                    return Err(Error::DeadlineExceeded);
+                }
+
+                if !state.pending.contains_key(&id) {
+                    state.sequence += 1;
+                    let rank = (Reverse(schedule.order), state.sequence);
+                    state.insert(id, frame, schedule.group, rank);
+
+                    self.ready.notify_one();
+                    return Ok(());
+                }
+
+                state.writers.entry(id).or_default().clone()
+            };
+
+            // The permit is stored if the frame is sent before we start waiting.
+            notify.notified().await;
+        }
+    }
+
+    // Update the schedule of any frame still queued for the stream.
+    pub fn reschedule(&self, id: StreamId, schedule: Schedule) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pending) = state.remove(id) {
+            let rank = (Reverse(schedule.order), pending.rank.1);
+            state.insert(id, pending.frame, schedule.group, rank);
+        }
+    }
+
+    // Drop any frame still queued for the stream, used when it's reset.
+    pub fn cancel(&self, id: StreamId) {
+        let mut state = self.state.lock().unwrap();
+        state.deadlines.remove(&id);
+        state.remove(id);
+    }
+
+    // Start tracking whether the stream writes its FIN before the deadline.
+    pub fn set_deadline(&self, id: StreamId) {
+        self.state.lock().unwrap().deadlines.entry(id).or_default();
+    }
+
+    // Called when the deadline is reached, returning true if the stream should be reset.
+    // Any queued frame is dropped and future writes will fail.
+    pub fn expire(&self, id: StreamId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.deadlines.get_mut(&id) {
+            Some(deadline) if deadline.dropped => {
+                state.deadlines.remove(&id);
+            }
+            Some(deadline) if !deadline.expired => deadline.expired = true,
+            _ => return false,
+        }
+
+        // Also wakes a blocked writer, so it sees the deadline expired.
+        state.remove(id);
+
+        true
+    }
+
+    // Returns true if the stream was reset because the deadline expired.
+    pub fn is_expired(&self, id: StreamId) -> bool {
+        let state = self.state.lock().unwrap();
+        state.deadlines.get(&id).is_some_and(|d| d.expired)
+    }
+
+    // Called when the SendStream is dropped, so we can forget about its deadline once it's no longer needed.
+    pub fn drop_stream(&self, id: StreamId) {
+        let mut state = self.state.lock().unwrap();
+        match state.deadlines.get_mut(&id) {
+            Some(deadline) if deadline.expired => {
+                state.deadlines.remove(&id);
+            }
+            Some(deadline) => deadline.dropped = true,
+            None => {}
+        }
+    }
+
+    // Wait for the next frame to send, or None if the queue is closed.
+    pub async fn next(&self) -> Option<Frame> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return None;
+                }
+
+                if let Some(frame) = state.pop() {
+                    return Some(frame);
+                }
+            }
+
+            // The permit is stored if a frame is queued before we start waiting.
+            self.ready.notified().await;
+        }
+    }
+
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+
+        for (_, notify) in state.writers.drain() {
+            notify.notify_one();
+        }
+
+        self.ready.notify_one();
+    }
+}
+
+impl OutboundState {
+    fn insert(&mut self, id: StreamId, frame: Frame, group: u64, rank: Rank) {
+        self.groups.entry(group).or_default().insert(rank, id);
+        self.pending.insert(id, Pending { frame, group, rank });
+    }
+
+    // Remove the stream's queued frame, waking its writer.
+    fn remove(&mut self, id: StreamId) -> Option<Pending> {
+        let pending = self.pending.remove(&id)?;
+
+        if let Some(streams) = self.groups.get_mut(&pending.group) {
+            streams.remove(&pending.rank);
+            if streams.is_empty() {
+                self.groups.remove(&pending.group);
+            }
+        }
+
+        if let Some(notify) = self.writers.remove(&id) {
+            notify.notify_one();
+        }
+
+        Some(pending)
+    }
+
+    fn pop(&mut self) -> Option<Frame> {
+        // Pick the next group after the last one to send, wrapping around to the lowest.
+        let after = (Bound::Excluded(self.last_group), Bound::Unbounded);
+        let group = self
+            .groups
+            .range(after)
+            .next()
+            .or_else(|| self.groups.iter().next())
+            .map(|(group, _)| *group)?;
+
+        // Within the group, pick the highest priority, then the oldest frame.
+        let id = *self.groups[&group].values().next()?;
+
+        self.last_group = group;
+        let frame = self.remove(id)?.frame;
+
+        // The stream is fully written, so the deadline no longer applies.
+        if let Frame::Stream(stream) = &frame {
+            if stream.fin {
+                self.deadlines.remove(&id);
+            }
+        }
+
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{frame, StreamDir};
+
+    fn stream(id: u64) -> StreamId {
+        StreamId::new(id, StreamDir::Uni, false)
+    }
+
+    fn frame(id: StreamId) -> Frame {
+        Frame::Stream(frame::Stream {
+            id,
+            data: Bytes::new(),
+            fin: false,
+        })
+    }
+
+    async fn send(outbound: &Outbound, id: u64, group: u64, order: i32) {
+        let id = stream(id);
+        let schedule = Schedule { group, order };
+        outbound.send(id, frame(id), schedule).await.unwrap();
+    }
+
+    async fn next(outbound: &Outbound) -> StreamId {
+        match outbound.next().await.unwrap() {
+            Frame::Stream(frame) => frame.id,
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let outbound = Outbound::default();
+
+        // Group 1 has more streams, but each group takes turns.
+        send(&outbound, 0, 1, 0).await;
+        send(&outbound, 1, 1, 0).await;
+        send(&outbound, 2, 1, 0).await;
+        send(&outbound, 3, 2, 0).await;
+
+        assert_eq!(next(&outbound).await, stream(0));
+        assert_eq!(next(&outbound).await, stream(3));
+        assert_eq!(next(&outbound).await, stream(1));
+        assert_eq!(next(&outbound).await, stream(2));
+    }
+
+    #[tokio::test]
+    async fn test_ungrouped() {
+        let outbound = Outbound::default();
+
+        // Streams without a group share group 0, so a high priority doesn't starve other groups.
+        send(&outbound, 0, 0, 100).await;
+        send(&outbound, 1, 0, 100).await;
+        send(&outbound, 2, 1, 0).await;
+
+        // Groups take turns, starting with the one after group 0.
+        assert_eq!(next(&outbound).await, stream(2));
+        assert_eq!(next(&outbound).await, stream(0));
+        assert_eq!(next(&outbound).await, stream(1));
+    }
+
+    #[tokio::test]
+    async fn test_priority() {
+        let outbound = Outbound::default();
+
+        // Within a group, the highest priority is sent first, then the oldest frame.
+        send(&outbound, 0, 1, 0).await;
+        send(&outbound, 1, 1, 10).await;
+        send(&outbound, 2, 1, 0).await;
+
+        assert_eq!(next(&outbound).await, stream(1));
+        assert_eq!(next(&outbound).await, stream(0));
+        assert_eq!(next(&outbound).await, stream(2));
+    }
+
+    #[tokio::test]
+    async fn test_blocked_writer() {
+        let outbound = Outbound::default();
+        send(&outbound, 0, 0, 0).await;
+
+        // The stream's previous frame is still queued, so the next write blocks.
+        let blocked = tokio::spawn({
+            let outbound = outbound.clone();
+            async move { send(&outbound, 0, 0, 0).await }
+        });
+
+        // Queuing another stream doesn't unblock it.
+        send(&outbound, 1, 0, 0).await;
+        tokio::task::yield_now().await;
+        assert!(!blocked.is_finished());
+
+        // Sending the previous frame does.
+        assert_eq!(next(&outbound).await, stream(0));
+        blocked.await.unwrap();
+
+        assert_eq!(next(&outbound).await, stream(1));
+        assert_eq!(next(&outbound).await, stream(0));
+    }
+
+    #[tokio::test]
+    async fn test_reschedule() {
+        let outbound = Outbound::default();
+
+        send(&outbound, 0, 1, 0).await;
+        send(&outbound, 1, 1, 0).await;
+
+        // A queued frame moves with its stream.
+        outbound.reschedule(stream(1), Schedule { group: 1, order: 5 });
+
+        assert_eq!(next(&outbound).await, stream(1));
+        assert_eq!(next(&outbound).await, stream(0));
+    }
+}
@@ -7,7 +7,7 @@ use std::{
 };
 
 use crate::{tungstenite, ConnectionClose, ResetStream, StopSending, Stream, StreamDir, ALPN};
-use crate::{Error, Frame, StreamId};
+use crate::{Error, Frame, Outbound, Schedule, StreamId};
 use bytes::{Buf, BufMut, Bytes};
 use futures::{SinkExt, StreamExt};
 use tokio::{
@@ -23,7 +23,7 @@ use web_transport_trait as generic;
 pub struct Session {
     is_server: bool,
 
-    outbound: mpsc::Sender<Frame>,
+    outbound: Outbound,
     outbound_priority: mpsc::UnboundedSender<Frame>,
 
     accept_bi: Arc<tokio::sync::Mutex<mpsc::Receiver<(SendStream, RecvStream)>>>,
@@ -34,6 +34,7 @@ pub struct Session {
 
     create_uni_id: Arc<AtomicU64>,
     create_bi_id: Arc<AtomicU64>,
+    send_group_id: Arc<AtomicU64>,
 
     closed: watch::Sender<Option<Error>>,
 }
@@ -42,7 +43,7 @@ struct SessionState<T> {
     ws: T,
     is_server: bool,
 
-    outbound: (mpsc::Sender<Frame>, mpsc::Receiver<Frame>),
+    outbound: Outbound,
     outbound_priority: (mpsc::UnboundedSender<Frame>, mpsc::UnboundedReceiver<Frame>),
 
     accept_bi: mpsc::Sender<(SendStream, RecvStream)>,
@@ -108,7 +109,7 @@ where
                         None => return Err(Error::Closed),
                     };
                 }
-                frame = self.outbound.1.recv() => {
+                frame = self.outbound.next() => {
                     match frame {
                         Some(frame) => self.send_frame(frame).await?,
                         None => return Err(Error::Closed),
@@ -173,6 +174,7 @@ where
                             inbound_reset: rx2,
                             outbound_priority: self.outbound_priority.0.clone(),
                             buffer: Bytes::new(),
+                            offset: 0,
                             closed: None,
                             fin: false,
                         };
@@ -192,12 +194,14 @@ where
 
                                 let send_frontend = SendStream {
                                     id: stream.id,
-                                    outbound: self.outbound.0.clone(),
+                                    outbound: self.outbound.clone(),
                                     outbound_priority: self.outbound_priority.0.clone(),
                                     inbound_stopped: rx,
+                                    schedule: Schedule::default(),
                                     offset: 0,
                                     closed: None,
                                     fin: false,
+                                    deadline: None,
                                 };
 
                                 self.send_streams.insert(stream.id, send_backend);
@@ -267,14 +271,14 @@ impl Session {
         let (create_uni_tx, create_uni_rx) = mpsc::channel(8);
         let (create_bi_tx, create_bi_rx) = mpsc::channel(8);
 
-        let (outbound_tx, outbound_rx) = mpsc::channel(8);
+        let outbound = Outbound::default();
         let (outbound_priority_tx, outbound_priority_rx) = mpsc::unbounded_channel();
 
         let closed = watch::Sender::new(None);
 
         let mut backend = SessionState {
             ws,
-            outbound: (outbound_tx.clone(), outbound_rx),
+            outbound: outbound.clone(),
             outbound_priority: (outbound_priority_tx.clone(), outbound_priority_rx),
             accept_bi: accept_bi_tx,
             accept_uni: accept_uni_tx,
@@ -287,12 +291,13 @@ impl Session {
         };
         tokio::spawn(async move {
             let err = backend.run().await.err().unwrap_or(Error::Closed);
+            backend.outbound.close();
             backend.closed.send(Some(err)).ok();
         });
 
         Session {
             is_server,
-            outbound: outbound_tx,
+            outbound,
             outbound_priority: outbound_priority_tx,
             accept_bi: Arc::new(tokio::sync::Mutex::new(accept_bi_rx)),
             accept_uni: Arc::new(tokio::sync::Mutex::new(accept_uni_rx)),
@@ -300,10 +305,13 @@ impl Session {
             create_bi: create_bi_tx,
             create_uni_id: Default::default(),
             create_bi_id: Default::default(),
+            send_group_id: Default::default(),
             closed,
         }
     }
 
+    // The callback signature is dictated by tungstenite.
+    #[allow(clippy::result_large_err)]
     pub async fn accept<T: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         socket: T,
     ) -> Result<Session, Error> {
@@ -348,6 +356,24 @@ impl Session {
         let (ws_stream, _) = tokio_tungstenite::connect_async(request).await?;
         Ok(Session::new(ws_stream, false))
     }
+
+    /// Create a new [SendGroup], which streams can join with [`SendStream::set_send_group`].
+    ///
+    /// Groups take turns sending, while streams within a group are sent in priority order.
+    pub fn send_group(&self) -> SendGroup {
+        // Group 0 is reserved for streams without a group.
+        let id = self.send_group_id.fetch_add(1, Ordering::Relaxed) + 1;
+        SendGroup { id }
+    }
+}
+
+/// A group of streams that share bandwidth fairly with other groups, created by [`Session::send_group`].
+///
+/// This mirrors `sendGroup` in the W3C WebTransport API.
+/// Streams without a group are scheduled as if they were in their own group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SendGroup {
+    id: u64,
 }
 
 impl generic::Session for Session {
@@ -386,9 +412,11 @@ impl generic::Session for Session {
             outbound: self.outbound.clone(),
             outbound_priority: self.outbound_priority.clone(),
             inbound_stopped: rx,
+            schedule: Schedule::default(),
             offset: 0,
             closed: None,
             fin: false,
+            deadline: None,
         };
 
         self.create_uni
@@ -414,9 +442,11 @@ impl generic::Session for Session {
             outbound: self.outbound.clone(),
             outbound_priority: self.outbound_priority.clone(),
             inbound_stopped: rx,
+            schedule: Schedule::default(),
             offset: 0,
             closed: None,
             fin: false,
+            deadline: None,
         };
 
         let (tx, rx) = mpsc::unbounded_channel();
@@ -430,6 +460,7 @@ impl generic::Session for Session {
             inbound_reset: rx2,
             outbound_priority: self.outbound_priority.clone(),
             buffer: Bytes::new(),
+            offset: 0,
             closed: None,
             fin: false,
         };
@@ -487,16 +518,37 @@ struct SendState {
 pub struct SendStream {
     id: StreamId,
 
-    outbound: mpsc::Sender<Frame>,                   // STREAM
+    outbound: Outbound,                              // STREAM
     outbound_priority: mpsc::UnboundedSender<Frame>, // RESET_STREAM
     inbound_stopped: mpsc::UnboundedReceiver<StopSending>,
 
+    schedule: Schedule,
     offset: u64,
     closed: Option<Error>,
     fin: bool,
+
+    // Resets the stream if it's not fully written by the deadline.
+    deadline: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl SendStream {
+    /// Move the stream into a [SendGroup], or out of one with `None`.
+    ///
+    /// The stream keeps its priority, which now orders it relative to other streams in the group.
+    pub fn set_send_group(&mut self, group: Option<&SendGroup>) {
+        self.schedule.group = group.map(|group| group.id).unwrap_or(0);
+        self.outbound.reschedule(self.id, self.schedule);
+    }
+
+    // Remember if the stream was reset because of the deadline.
+    fn send_error(&mut self, err: Error) -> Error {
+        if let Error::DeadlineExceeded = err {
+            self.closed = Some(err.clone());
+        }
+
+        err
+    }
+
     fn recv_stop(&mut self, code: VarInt) -> Error {
         if let Some(error) = &self.closed {
             return error.clone();
@@ -506,6 +558,8 @@ impl SendStream {
 
         let error = Error::StreamStop(code);
 
+        // Don't send any queued data after the reset.
+        self.outbound.cancel(self.id);
         self.outbound_priority.send(frame.into()).ok();
         self.closed = Some(error.clone());
 
@@ -518,6 +572,9 @@ impl Drop for SendStream {
         if !self.fin && self.closed.is_none() {
             generic::SendStream::reset(self, 0);
         }
+
+        // NOTE: The deadline task keeps running until the FIN is written.
+        self.outbound.drop_stream(self.id);
     }
 }
 
@@ -548,9 +605,9 @@ impl generic::SendStream for SendStream {
         };
 
         tokio::select! {
-            result = self.outbound.send(frame.into()) => {
-                if result.is_err() {
-                    return Err(Error::Closed);
+            res = self.outbound.send(self.id, frame.into(), self.schedule) => {
+                if let Err(err) = res {
+                    return Err(self.send_error(err));
                 }
                 self.offset += size as u64;
                 Ok(size)
@@ -561,8 +618,36 @@ impl generic::SendStream for SendStream {
         }
     }
 
-    fn set_priority(&mut self, _priority: i32) {
-        // Priority not implemented in this version
+    fn set_deadline(&mut self, deadline: std::time::Instant, code: u32) {
+        // Like reset, there's nothing to do once the stream is finished or closed.
+        if self.fin || self.closed.is_some() {
+            return;
+        }
+
+        if let Some(task) = self.deadline.take() {
+            task.abort();
+        }
+
+        self.outbound.set_deadline(self.id);
+
+        let id = self.id;
+        let code = VarInt::from(code);
+        let outbound = self.outbound.clone();
+        let outbound_priority = self.outbound_priority.clone();
+
+        self.deadline = Some(tokio::spawn(async move {
+            tokio::time::sleep_until(deadline.into()).await;
+
+            if outbound.expire(id) {
+                let frame = ResetStream { id, code };
+                outbound_priority.send(frame.into()).ok();
+            }
+        }));
+    }
+
+    fn set_priority(&mut self, priority: i32) {
+        self.schedule.order = priority;
+        self.outbound.reschedule(self.id, self.schedule);
     }
 
     fn reset(&mut self, code: u32) {
@@ -570,9 +655,16 @@ impl generic::SendStream for SendStream {
             return;
         }
 
+        // The deadline already reset the stream.
+        if self.outbound.is_expired(self.id) {
+            self.closed = Some(Error::DeadlineExceeded);
+            return;
+        }
+
         let code = VarInt::from(code);
         let frame = ResetStream { id: self.id, code };
 
+        self.outbound.cancel(self.id);
         self.outbound_priority.send(frame.into()).ok();
         self.closed = Some(Error::StreamReset(code));
     }
@@ -588,10 +680,13 @@ impl generic::SendStream for SendStream {
             fin: true,
         };
 
-        self.outbound
-            .send(frame.into())
+        if let Err(err) = self
+            .outbound
+            .send(self.id, frame.into(), self.schedule)
             .await
-            .map_err(|_| Error::Closed)?;
+        {
+            return Err(self.send_error(err));
+        }
         self.fin = true;
 
         Ok(())
@@ -625,6 +720,9 @@ pub struct RecvStream {
 
     buffer: Bytes,
 
+    // The number of bytes read so far, used as the offset for unordered reads.
+    offset: u64,
+
     closed: Option<Error>,
     fin: bool,
 }
@@ -655,6 +753,7 @@ impl generic::RecvStream for RecvStream {
         loop {
             if !self.buffer.is_empty() {
                 let to_read = max.min(self.buffer.len());
+                self.offset += to_read as u64;
                 return Ok(Some(self.buffer.split_to(to_read)));
             }
 
@@ -686,6 +785,7 @@ impl generic::RecvStream for RecvStream {
     ) -> Result<Option<usize>, Self::Error> {
         if !self.buffer.is_empty() {
             let to_read = buf.remaining_mut().min(self.buffer.len());
+            self.offset += to_read as u64;
             buf.put(self.buffer.split_to(to_read));
             return Ok(Some(to_read));
         }
@@ -704,6 +804,16 @@ impl generic::RecvStream for RecvStream {
         self.read_buf(&mut buf).await
     }
 
+    // WebSockets are ordered, so this is just read_chunk with the offset.
+    async fn read_chunk_unordered(
+        &mut self,
+        max: usize,
+    ) -> Result<Option<generic::Chunk>, Self::Error> {
+        let offset = self.offset;
+        let chunk = self.read_chunk(max).await?;
+        Ok(chunk.map(|bytes| generic::Chunk { offset, bytes }))
+    }
+
     fn stop(&mut self, code: u32) {
         let code = VarInt::from(code);
         let frame = StopSending { id: self.id, code };
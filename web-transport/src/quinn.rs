@@ -76,15 +76,18 @@ impl From<quinn::Server> for Server {
 
 impl Server {
     /// Accept an incoming connection.
+    ///
+    /// Connections using a custom ALPN are returned as raw QUIC sessions, see [quinn::ServerBuilder::with_alpns].
     pub async fn accept(&mut self) -> Result<Option<Session>, Error> {
         match self.inner.accept().await {
-            Some(session) => Ok(Some(
-                session
+            Some(quinn::Incoming::WebTransport(request)) => Ok(Some(
+                request
                     .ok()
                     .await
                     .map_err(|e| Error::Write(e.into()))?
                     .into(),
             )),
+            Some(quinn::Incoming::Raw { session, .. }) => Ok(Some(session.into())),
             None => Ok(None),
         }
     }
@@ -128,6 +131,15 @@ impl Session {
         Ok(self.inner.open_uni().await.map(SendStream::new)?)
     }
 
+    /// Create a new [SendGroup], which streams can join with [SendStream::set_send_group].
+    ///
+    /// Each group gets a fair share of bandwidth, while streams within a group are sent in priority order.
+    pub fn send_group(&self) -> Result<SendGroup, Error> {
+        Ok(SendGroup {
+            inner: self.inner.send_group(),
+        })
+    }
+
     /// Send a datagram over the network.
     ///
     /// QUIC datagrams may be dropped for any reason:
@@ -175,6 +187,12 @@ impl From<quinn::Session> for Session {
     }
 }
 
+/// A group of streams that share bandwidth fairly with other groups, created by [Session::send_group].
+#[derive(Clone)]
+pub struct SendGroup {
+    inner: quinn::SendGroup,
+}
+
 /// An outgoing stream of bytes to the peer.
 ///
 /// QUIC streams have flow control, which means the send rate is limited by the peer's receive window.
@@ -210,6 +228,13 @@ impl SendStream {
         self.inner.set_priority(order).ok();
     }
 
+    /// Move the stream into a [SendGroup], or out of one with `None`.
+    ///
+    /// The priority now orders the stream relative to other streams in the group.
+    pub fn set_send_group(&mut self, group: Option<&SendGroup>) {
+        self.inner.set_send_group(group.map(|g| &g.inner)).ok();
+    }
+
     /// Send an immediate reset code, closing the stream.
     pub fn reset(&mut self, code: u32) {
         self.inner.reset(code).ok();
@@ -262,6 +287,18 @@ impl RecvStream {
             .map(|chunk| chunk.bytes))
     }
 
+    /// Read the next chunk of data in any order, returning it along with its offset in the stream.
+    ///
+    /// A lost packet won't block later data, which is useful when late data is useless.
+    /// Don't mix this with ordered reads on the same stream, otherwise an error is returned.
+    pub async fn read_unordered(&mut self, max: usize) -> Result<Option<(u64, Bytes)>, Error> {
+        Ok(self
+            .inner
+            .read_chunk(max, false)
+            .await?
+            .map(|chunk| (chunk.offset, chunk.bytes)))
+    }
+
     /// Read some data into the provided buffer.
     ///
     /// The number of bytes read is returned, or None if the stream is closed.
@@ -326,6 +363,12 @@ impl From<quinn::WriteError> for Error {
         }
     }
 }
+impl From<quinn::SendDatagramError> for Error {
+    fn from(e: quinn::SendDatagramError) -> Self {
+        Error::Session(e.into())
+    }
+}
+
 impl From<quinn::ReadError> for Error {
     fn from(e: quinn::ReadError) -> Self {
         match e {
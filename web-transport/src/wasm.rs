@@ -64,17 +64,17 @@ pub struct Session(web_transport_wasm::Session);
 impl Session {
     pub async fn accept_uni(&mut self) -> Result<RecvStream, Error> {
         let stream = self.0.accept_uni().await?;
-        Ok(RecvStream(stream))
+        Ok(RecvStream::new(stream))
     }
 
     pub async fn accept_bi(&mut self) -> Result<(SendStream, RecvStream), Error> {
         let (s, r) = self.0.accept_bi().await?;
-        Ok((SendStream(s), RecvStream(r)))
+        Ok((SendStream(s), RecvStream::new(r)))
     }
 
     pub async fn open_bi(&mut self) -> Result<(SendStream, RecvStream), Error> {
         let (s, r) = self.0.open_bi().await?;
-        Ok((SendStream(s), RecvStream(r)))
+        Ok((SendStream(s), RecvStream::new(r)))
     }
 
     pub async fn open_uni(&mut self) -> Result<SendStream, Error> {
@@ -90,6 +90,11 @@ impl Session {
         self.0.closed().await
     }
 
+    /// Create a new [SendGroup], which streams can join with [SendStream::set_send_group].
+    pub fn send_group(&self) -> Result<SendGroup, Error> {
+        self.0.send_group().map(SendGroup)
+    }
+
     /// Send a datagram.
     pub async fn send_datagram(&mut self, payload: Bytes) -> Result<(), Error> {
         self.0.send_datagram(payload).await
@@ -111,6 +116,10 @@ impl From<web_transport_wasm::Session> for Session {
     }
 }
 
+/// A group of streams that share bandwidth fairly with other groups, created by [Session::send_group].
+#[derive(Clone)]
+pub struct SendGroup(web_transport_wasm::SendGroup);
+
 pub struct SendStream(web_transport_wasm::SendStream);
 
 impl SendStream {
@@ -130,6 +139,11 @@ impl SendStream {
         self.0.set_priority(order)
     }
 
+    /// Move the stream into a [SendGroup], or out of one with `None`.
+    pub fn set_send_group(&mut self, group: Option<&SendGroup>) {
+        self.0.set_send_group(group.map(|g| &g.0))
+    }
+
     /// Send a QUIC reset code.
     pub fn reset(&mut self, code: u32) {
         self.0.reset(&code.to_string())
@@ -149,27 +163,52 @@ impl SendStream {
     }
 }
 
-pub struct RecvStream(web_transport_wasm::RecvStream);
+pub struct RecvStream {
+    inner: web_transport_wasm::RecvStream,
+
+    // The number of bytes read so far, used as the offset for unordered reads.
+    offset: u64,
+}
 
 impl RecvStream {
+    fn new(inner: web_transport_wasm::RecvStream) -> Self {
+        Self { inner, offset: 0 }
+    }
+
     /// Attempt to read a chunk of unbuffered data.
     pub async fn read(&mut self, max: usize) -> Result<Option<Bytes>, Error> {
-        self.0.read(max).await
+        let data = self.inner.read(max).await?;
+        if let Some(data) = &data {
+            self.offset += data.len() as u64;
+        }
+        Ok(data)
     }
 
     /// Attempt to read from the stream into the given buffer.
     pub async fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Result<Option<usize>, Error> {
-        self.0.read_buf(buf).await
+        let size = self.inner.read_buf(buf).await?;
+        if let Some(size) = size {
+            self.offset += size as u64;
+        }
+        Ok(size)
+    }
+
+    /// Attempt to read a chunk of data in any order, returning it along with its offset in the stream.
+    ///
+    /// The browser doesn't support unordered reads, so this always returns data in order.
+    pub async fn read_unordered(&mut self, max: usize) -> Result<Option<(u64, Bytes)>, Error> {
+        let offset = self.offset;
+        Ok(self.read(max).await?.map(|data| (offset, data)))
     }
 
     /// Send a `STOP_SENDING` QUIC code.
     pub fn stop(&mut self, code: u32) {
-        self.0.stop(&code.to_string())
+        self.inner.stop(&code.to_string())
     }
 
     /// Block until the stream has been closed and return the error code, if any.
     pub async fn closed(&mut self) -> Result<Option<u8>, Error> {
-        self.0.closed().await
+        self.inner.closed().await
     }
 }
 